@@ -1,17 +1,78 @@
+use crate::book_manager::LayoutChanged;
+use crate::covers::NotAnImage;
+use crate::library::DuplicateBook;
+use crate::network::NetworkError;
+use crate::opds::NotInRecentResults;
+use crate::search::QueryError;
 use axum::{
     http::StatusCode,
     response::{IntoResponse, Response},
+    Json,
 };
+use serde_json::json;
 
 // Make our own error that wraps `anyhow::Error`.
 pub struct AppError(anyhow::Error);
 
+impl AppError {
+    /// The HTTP status and machine-readable code describing this error.
+    ///
+    /// Network failures anywhere in the error chain are reported by their kind, so
+    /// clients can tell a missing book from a blocked or unreachable upstream.
+    fn status_and_code(&self) -> (StatusCode, &'static str) {
+        if self.0.downcast_ref::<QueryError>().is_some() {
+            return (StatusCode::BAD_REQUEST, "invalid_query");
+        }
+        if self.0.downcast_ref::<LayoutChanged>().is_some() {
+            return (StatusCode::BAD_GATEWAY, "upstream_layout_changed");
+        }
+        if self.0.downcast_ref::<DuplicateBook>().is_some() {
+            return (StatusCode::CONFLICT, "duplicate");
+        }
+        if self.0.downcast_ref::<NotInRecentResults>().is_some() {
+            return (StatusCode::NOT_FOUND, "search_again");
+        }
+        if self.0.downcast_ref::<NotAnImage>().is_some() {
+            return (StatusCode::BAD_GATEWAY, "upstream_not_an_image");
+        }
+        match self.0.downcast_ref::<NetworkError>() {
+            Some(e) => network_status_and_code(e),
+            None => (StatusCode::INTERNAL_SERVER_ERROR, "internal_error"),
+        }
+    }
+}
+
+fn network_status_and_code(error: &NetworkError) -> (StatusCode, &'static str) {
+    match error {
+        NetworkError::Timeout { .. } => (StatusCode::GATEWAY_TIMEOUT, "upstream_timeout"),
+        NetworkError::Dns { .. } => (StatusCode::BAD_GATEWAY, "upstream_dns"),
+        NetworkError::Connect { .. } => (StatusCode::BAD_GATEWAY, "upstream_connect"),
+        NetworkError::Status { code: 404, .. } => (StatusCode::NOT_FOUND, "not_found"),
+        NetworkError::Status { code: 429, .. } => {
+            (StatusCode::TOO_MANY_REQUESTS, "upstream_rate_limited")
+        }
+        NetworkError::Status { .. } => (StatusCode::BAD_GATEWAY, "upstream_status"),
+        NetworkError::Challenge { .. } => (StatusCode::SERVICE_UNAVAILABLE, "upstream_challenge"),
+        NetworkError::BodyRead { .. } => (StatusCode::BAD_GATEWAY, "upstream_body_read"),
+        NetworkError::RetriesExhausted { last, .. } => match last.as_deref() {
+            Some(last) => (network_status_and_code(last).0, "retries_exhausted"),
+            None => (StatusCode::BAD_GATEWAY, "retries_exhausted"),
+        },
+        NetworkError::CircuitOpen { .. } => (StatusCode::SERVICE_UNAVAILABLE, "circuit_open"),
+        NetworkError::InvalidUrl { .. } => (StatusCode::INTERNAL_SERVER_ERROR, "invalid_url"),
+    }
+}
+
 // Tell axum how to convert `AppError` into a response.
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let (status, code) = self.status_and_code();
         (
-            StatusCode::INTERNAL_SERVER_ERROR,
-            format!("Something went wrong: {}", self.0),
+            status,
+            Json(json!({
+                "error": format!("{:#}", self.0),
+                "code": code,
+            })),
         )
             .into_response()
     }
@@ -27,3 +88,111 @@ where
         Self(err.into())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::library::{Duplicate, Location, MatchedBy};
+    use anyhow::Context;
+
+    fn status_and_code(error: NetworkError) -> (StatusCode, &'static str) {
+        AppError::from(error).status_and_code()
+    }
+
+    #[test]
+    fn test_network_errors_map_to_status_and_code() {
+        let url = "https://example.com".to_string();
+        assert_eq!(
+            status_and_code(NetworkError::Timeout { url: url.clone() }),
+            (StatusCode::GATEWAY_TIMEOUT, "upstream_timeout")
+        );
+        assert_eq!(
+            status_and_code(NetworkError::Status {
+                url: url.clone(),
+                code: 404
+            }),
+            (StatusCode::NOT_FOUND, "not_found")
+        );
+        assert_eq!(
+            status_and_code(NetworkError::Challenge { url: url.clone() }),
+            (StatusCode::SERVICE_UNAVAILABLE, "upstream_challenge")
+        );
+        assert_eq!(
+            status_and_code(NetworkError::RetriesExhausted {
+                url: url.clone(),
+                attempts: 3,
+                last: Some(Box::new(NetworkError::Timeout { url })),
+            }),
+            (StatusCode::GATEWAY_TIMEOUT, "retries_exhausted")
+        );
+    }
+
+    #[test]
+    fn test_network_error_found_behind_context() {
+        let error = Err::<(), _>(NetworkError::CircuitOpen {
+            host: "example.com".to_string(),
+        })
+        .context("All mirrors failed")
+        .unwrap_err();
+
+        assert_eq!(
+            AppError(error).status_and_code(),
+            (StatusCode::SERVICE_UNAVAILABLE, "circuit_open")
+        );
+    }
+
+    #[test]
+    fn test_invalid_queries_are_bad_requests() {
+        let error = AppError::from(QueryError {
+            parameter: "sort",
+            value: "best".to_string(),
+            message: "unknown order".to_string(),
+        });
+        assert_eq!(
+            error.status_and_code(),
+            (StatusCode::BAD_REQUEST, "invalid_query")
+        );
+    }
+
+    #[test]
+    fn test_layout_changes_are_bad_gateway() {
+        let error = AppError::from(LayoutChanged {
+            path: "/search?q=dune".to_string(),
+            reason: "no results",
+        });
+        assert_eq!(
+            error.status_and_code(),
+            (StatusCode::BAD_GATEWAY, "upstream_layout_changed")
+        );
+    }
+
+    #[test]
+    fn test_duplicates_are_conflicts() {
+        let error = AppError::from(DuplicateBook(Duplicate {
+            location: Location::Calibre,
+            matched_by: MatchedBy::Isbn,
+            title: "Dune".to_string(),
+        }));
+        assert_eq!(error.status_and_code(), (StatusCode::CONFLICT, "duplicate"));
+    }
+
+    #[test]
+    fn test_forgotten_opds_books_are_not_found() {
+        let error = AppError::from(NotInRecentResults {
+            id: "urn:uuid:1".to_string(),
+        });
+        assert_eq!(
+            error.status_and_code(),
+            (StatusCode::NOT_FOUND, "search_again")
+        );
+    }
+
+    #[test]
+    fn test_other_errors_are_internal() {
+        let error = AppError(anyhow::anyhow!("parse failure"));
+        assert_eq!(
+            error.status_and_code(),
+            (StatusCode::INTERNAL_SERVER_ERROR, "internal_error")
+        );
+    }
+}
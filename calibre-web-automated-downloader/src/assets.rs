@@ -0,0 +1,294 @@
+use axum::body::Body;
+use axum::extract::Path;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::response::{IntoResponse, Response};
+use brotli::enc::BrotliEncoderParams;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use once_cell::sync::Lazy;
+use rust_embed::RustEmbed;
+use std::collections::HashMap;
+use std::io::Write;
+
+/// The frontend under `static/`, compiled into the binary.
+#[derive(RustEmbed)]
+#[folder = "../static/"]
+struct Frontend;
+
+/// Content types worth compressing; images and icons are served as they are.
+const COMPRESSIBLE_TYPES: &[&str] = &[
+    "text/",
+    "application/javascript",
+    "application/json",
+    "image/svg+xml",
+];
+
+/// An embedded file with its headers and compressed variants prepared up front.
+struct Asset {
+    body: Vec<u8>,
+    content_type: String,
+    /// Content hash the ETags of all variants are derived from.
+    hash: String,
+    gzip: Option<Vec<u8>>,
+    brotli: Option<Vec<u8>>,
+}
+
+impl Asset {
+    fn new(path: &str) -> Option<Self> {
+        let file = Frontend::get(path)?;
+        let content_type = file.metadata.mimetype().to_string();
+        let hash: String = file.metadata.sha256_hash()[..16]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+
+        let body = file.data.into_owned();
+        let compressible = COMPRESSIBLE_TYPES
+            .iter()
+            .any(|prefix| content_type.starts_with(prefix));
+        // Only keep a variant if it actually saves bytes
+        let smaller = |compressed: Vec<u8>| (compressed.len() < body.len()).then_some(compressed);
+        let (gzip, brotli) = if compressible {
+            (
+                gzip(&body).and_then(smaller),
+                brotli(&body).and_then(smaller),
+            )
+        } else {
+            (None, None)
+        };
+
+        Some(Asset {
+            content_type,
+            hash,
+            body,
+            gzip,
+            brotli,
+        })
+    }
+}
+
+fn gzip(data: &[u8]) -> Option<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data).ok()?;
+    encoder.finish().ok()
+}
+
+fn brotli(data: &[u8]) -> Option<Vec<u8>> {
+    let mut compressed = Vec::new();
+    let params = BrotliEncoderParams {
+        quality: 11,
+        ..Default::default()
+    };
+    brotli::BrotliCompress(&mut &data[..], &mut compressed, &params).ok()?;
+    Some(compressed)
+}
+
+/// Every embedded file by path, compressed once by [`prepare`].
+static ASSETS: Lazy<HashMap<String, Asset>> = Lazy::new(|| {
+    Frontend::iter()
+        .filter_map(|path| Asset::new(&path).map(|asset| (path.into_owned(), asset)))
+        .collect()
+});
+
+/// Compress the embedded files at startup, on a blocking thread, so that the first
+/// request does not stall the runtime on brotli's highest quality.
+pub async fn prepare() {
+    let start = std::time::Instant::now();
+    match tokio::task::spawn_blocking(|| Lazy::force(&ASSETS).len()).await {
+        Ok(count) => tracing::debug!(
+            latency_ms = start.elapsed().as_millis() as u64,
+            "Compressed {} embedded files",
+            count
+        ),
+        Err(e) => tracing::error!("Failed to compress embedded files: {}", e),
+    }
+}
+
+/// The encodings the client accepts, ignoring those it explicitly refuses with `q=0`.
+fn accepted_encodings(headers: &HeaderMap) -> Vec<String> {
+    headers
+        .get_all(header::ACCEPT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|entry| {
+            let mut parts = entry.split(';').map(str::trim);
+            let encoding = parts.next()?.to_lowercase();
+            let refused = parts.any(|param| {
+                param
+                    .strip_prefix("q=")
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .is_some_and(|q| q == 0.0)
+            });
+            (!refused).then_some(encoding)
+        })
+        .collect()
+}
+
+/// Whether `If-None-Match` lists `etag` (or `*`).
+fn etag_matches(headers: &HeaderMap, etag: &str) -> bool {
+    headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| {
+            value
+                .split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == etag || tag == "*")
+        })
+}
+
+/// Serve the embedded file at `path`, choosing brotli or gzip when the client accepts them.
+fn serve_asset(path: &str, headers: &HeaderMap) -> Response {
+    let Some(asset) = ASSETS.get(path) else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let encodings = accepted_encodings(headers);
+    let accepts = |name: &str| encodings.iter().any(|e| e == name || e == "*");
+    // Each variant has its own strong ETag, as the bytes differ
+    let (body, encoding, etag_suffix) = match (&asset.brotli, &asset.gzip) {
+        (Some(br), _) if accepts("br") => (br, Some("br"), "-br"),
+        (_, Some(gz)) if accepts("gzip") => (gz, Some("gzip"), "-gz"),
+        _ => (&asset.body, None, ""),
+    };
+    let etag = format!("\"{}{}\"", asset.hash, etag_suffix);
+
+    // The page itself must be revalidated so new builds show up; the rest may be cached briefly
+    let cache_control = if path == "index.html" {
+        "no-cache"
+    } else {
+        "public, max-age=3600"
+    };
+
+    let mut response = if etag_matches(headers, &etag) {
+        StatusCode::NOT_MODIFIED.into_response()
+    } else {
+        let mut response = Response::new(Body::from(body.clone()));
+        response.headers_mut().insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_str(&asset.content_type)
+                .unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        );
+        if let Some(encoding) = encoding {
+            response
+                .headers_mut()
+                .insert(header::CONTENT_ENCODING, HeaderValue::from_static(encoding));
+        }
+        response
+    };
+
+    let response_headers = response.headers_mut();
+    if let Ok(etag) = HeaderValue::from_str(&etag) {
+        response_headers.insert(header::ETAG, etag);
+    }
+    response_headers.insert(
+        header::CACHE_CONTROL,
+        HeaderValue::from_static(cache_control),
+    );
+    response_headers.insert(header::VARY, HeaderValue::from_static("Accept-Encoding"));
+    response
+}
+
+/// Serves the embedded `index.html`.
+pub async fn handler_index(headers: HeaderMap) -> Response {
+    serve_asset("index.html", &headers)
+}
+
+/// Serves the embedded favicon at the root of the app, where browsers look for it.
+pub async fn handler_favicon(headers: HeaderMap) -> Response {
+    serve_asset("media/favicon.ico", &headers)
+}
+
+/// Serves an embedded file below `/static`.
+pub async fn handler_static(Path(path): Path<String>, headers: HeaderMap) -> Response {
+    serve_asset(&path, &headers)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+    use axum::http::Request;
+    use axum::routing::get;
+    use axum::Router;
+    use tower::ServiceExt;
+
+    fn app() -> Router {
+        Router::new()
+            .route("/", get(handler_index))
+            .route("/static/{*path}", get(handler_static))
+    }
+
+    async fn get_with(uri: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::builder().uri(uri);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        app()
+            .oneshot(request.body(Body::empty()).unwrap())
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_prepare_compresses_up_front() {
+        prepare().await;
+        let index = &Lazy::get(&ASSETS).unwrap()["index.html"];
+        assert!(index.brotli.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_index_is_served_uncompressed_by_default() {
+        let response = get_with("/", &[]).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers()[header::CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        assert_eq!(response.headers()[header::CACHE_CONTROL], "no-cache");
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("<html"));
+    }
+
+    #[tokio::test]
+    async fn test_compressed_variants_are_negotiated() {
+        let br = get_with("/static/js/main.js", &[("accept-encoding", "gzip, br")]).await;
+        let gz = get_with("/static/js/main.js", &[("accept-encoding", "gzip, br;q=0")]).await;
+        let plain = get_with("/static/js/main.js", &[("accept-encoding", "identity")]).await;
+
+        assert_eq!(br.headers()[header::CONTENT_ENCODING], "br");
+        assert_eq!(gz.headers()[header::CONTENT_ENCODING], "gzip");
+        assert!(plain.headers().get(header::CONTENT_ENCODING).is_none());
+        assert_ne!(br.headers()[header::ETAG], gz.headers()[header::ETAG]);
+        assert_eq!(br.headers()[header::VARY], "Accept-Encoding");
+    }
+
+    #[tokio::test]
+    async fn test_images_are_not_compressed() {
+        let response = get_with("/static/media/logo.png", &[("accept-encoding", "br")]).await;
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(header::CONTENT_ENCODING).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_matching_etag_returns_not_modified() {
+        let first = get_with("/static/css/styles.css", &[]).await;
+        let etag = first.headers()[header::ETAG].to_str().unwrap().to_string();
+
+        let second = get_with("/static/css/styles.css", &[("if-none-match", &etag)]).await;
+
+        assert_eq!(second.status(), StatusCode::NOT_MODIFIED);
+        assert_eq!(second.headers()[header::ETAG], etag.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_missing_asset_is_not_found() {
+        let response = get_with("/static/missing.js", &[]).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
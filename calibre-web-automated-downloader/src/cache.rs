@@ -0,0 +1,501 @@
+use crate::config::Config;
+use crate::network::NetworkError;
+use anyhow::Result;
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// A cached page body and when it was fetched.
+#[derive(Clone, Debug)]
+struct CacheEntry {
+    body: String,
+    stored_at: SystemTime,
+}
+
+impl CacheEntry {
+    /// Bytes the entry takes up, counted against the cache's budget.
+    fn size(key: &str, body: &str) -> u64 {
+        (key.len() + body.len()) as u64
+    }
+
+    fn is_fresh(&self, ttl: Duration) -> bool {
+        self.stored_at.elapsed().is_ok_and(|age| age <= ttl)
+    }
+}
+
+/// The **data** behind the cache, guarded by the `Mutex` in `PageCache`.
+#[derive(Debug, Default)]
+struct PageCacheData {
+    entries: HashMap<String, CacheEntry>,
+    /// Keys from least to most recently used.
+    order: VecDeque<String>,
+    /// Bytes taken up by all entries.
+    total: u64,
+}
+
+/// Bounded cache of fetched HTML pages, keyed by URL.
+///
+/// Entries live in memory and, if a directory is configured, are also written to
+/// disk so they survive restarts. Once the entries take up more than `max_bytes` the
+/// least recently used ones are evicted from both. Expired entries are not served
+/// normally, but are kept around as a fallback for when the upstream host fails.
+#[derive(Debug)]
+pub struct PageCache {
+    data: Mutex<PageCacheData>,
+    /// How long an entry is served without asking upstream; 0 disables the cache.
+    ttl: Duration,
+    max_bytes: u64,
+    disk_dir: Option<PathBuf>,
+}
+
+impl PageCache {
+    /// A cache keeping up to `max_bytes` of pages, picking up the entries a previous
+    /// run left in `disk_dir`.
+    pub fn new(ttl: Duration, max_bytes: u64, disk_dir: Option<PathBuf>) -> Self {
+        let cache = PageCache {
+            data: Mutex::new(PageCacheData::default()),
+            ttl,
+            max_bytes,
+            disk_dir,
+        };
+        if let Some(dir) = &cache.disk_dir {
+            cache.load_from_disk(dir);
+        }
+        cache
+    }
+
+    /// Load the entries stored in `dir`, oldest first so that the least recently
+    /// written ones are evicted if they no longer fit. Files that are not entries of
+    /// this cache, or no longer fit, are removed.
+    fn load_from_disk(&self, dir: &Path) {
+        let mut files = std::fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let modified = entry.metadata().ok()?.modified().ok()?;
+                Some((modified, entry.path()))
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let mut evicted = Vec::new();
+        let mut data = self.data.lock().unwrap();
+        for (stored_at, path) in files {
+            let contents = std::fs::read_to_string(&path).unwrap_or_default();
+            match contents.split_once('\n') {
+                Some((key, body)) if self.disk_path(key).as_ref() == Some(&path) => {
+                    let entry = CacheEntry {
+                        body: body.to_string(),
+                        stored_at,
+                    };
+                    evicted.extend(self.insert_internal(&mut data, key, entry));
+                }
+                _ => {
+                    std::fs::remove_file(&path).ok();
+                }
+            }
+        }
+        drop(data);
+        for path in evicted.iter().filter_map(|key| self.disk_path(key)) {
+            std::fs::remove_file(path).ok();
+        }
+    }
+
+    /// The cache for search and book detail pages described by `config`.
+    pub fn from_config(config: &Config) -> Self {
+        PageCache::new(
+            Duration::from_secs(config.cache_ttl),
+            config.cache_size * 1024 * 1024,
+            config
+                .cache_on_disk
+                .then(|| config.tmp_dir.join("page-cache")),
+        )
+    }
+
+    /// Return the page for `key`, fetching it with `fetch` unless a fresh copy is cached.
+    ///
+    /// With `bypass` set the cache is not read, but the fresh result is still stored.
+    /// If `fetch` fails because the host is down or unreachable and an expired copy
+    /// exists, that stale copy is returned instead. Other errors, such as a 404, are
+    /// passed on.
+    pub async fn get_or_fetch<F, Fut>(&self, key: &str, bypass: bool, fetch: F) -> Result<String>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<String>>,
+    {
+        if self.ttl.is_zero() {
+            return fetch().await;
+        }
+
+        let cached = self.lookup(key).await;
+        if let Some(entry) = &cached {
+            if !bypass && entry.is_fresh(self.ttl) {
+                tracing::debug!("Cache hit for {}", key);
+                return Ok(entry.body.clone());
+            }
+        }
+
+        match fetch().await {
+            Ok(body) => {
+                self.insert(key, body.clone()).await;
+                Ok(body)
+            }
+            Err(e) => match cached {
+                Some(entry) if is_host_failure(&e) => {
+                    tracing::warn!("Upstream failed for {}, serving stale copy: {}", key, e);
+                    Ok(entry.body)
+                }
+                _ => Err(e),
+            },
+        }
+    }
+
+    /// Look `key` up in memory, falling back to disk. Marks the entry as recently used.
+    async fn lookup(&self, key: &str) -> Option<CacheEntry> {
+        {
+            let mut data = self.data.lock().unwrap();
+            if let Some(entry) = data.entries.get(key).cloned() {
+                Self::touch_internal(&mut data, key);
+                return Some(entry);
+            }
+        }
+
+        let entry = self.read_from_disk(key).await?;
+        let evicted = {
+            let mut data = self.data.lock().unwrap();
+            self.insert_internal(&mut data, key, entry.clone())
+        };
+        self.remove_from_disk(evicted).await;
+        Some(entry)
+    }
+
+    /// Store `body` under `key`, evicting the least recently used entries if needed.
+    async fn insert(&self, key: &str, body: String) {
+        let entry = CacheEntry {
+            body,
+            stored_at: SystemTime::now(),
+        };
+        let evicted = {
+            let mut data = self.data.lock().unwrap();
+            self.insert_internal(&mut data, key, entry.clone())
+        };
+        // An entry larger than the whole cache is evicted straight away
+        if !evicted.iter().any(|k| k == key) {
+            self.write_to_disk(key, &entry).await;
+        }
+        self.remove_from_disk(evicted).await;
+    }
+
+    /// Add or replace the entry for `key`; returns the keys evicted to make room.
+    fn insert_internal(
+        &self,
+        data: &mut PageCacheData,
+        key: &str,
+        entry: CacheEntry,
+    ) -> Vec<String> {
+        data.total += CacheEntry::size(key, &entry.body);
+        if let Some(old) = data.entries.insert(key.to_string(), entry) {
+            data.total -= CacheEntry::size(key, &old.body);
+        }
+        Self::touch_internal(data, key);
+        self.evict_internal(data)
+    }
+
+    /// Move `key` to the most recently used end.
+    fn touch_internal(data: &mut PageCacheData, key: &str) {
+        data.order.retain(|k| k != key);
+        data.order.push_back(key.to_string());
+    }
+
+    /// Drop least recently used entries until the cache fits; returns the evicted keys.
+    fn evict_internal(&self, data: &mut PageCacheData) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while data.total > self.max_bytes {
+            let Some(key) = data.order.pop_front() else {
+                break;
+            };
+            if let Some(entry) = data.entries.remove(&key) {
+                data.total -= CacheEntry::size(&key, &entry.body);
+            }
+            evicted.push(key);
+        }
+        evicted
+    }
+
+    /// The file an entry is stored in. Keys are hashed, so the key itself is kept
+    /// on the first line of the file to rule out collisions.
+    fn disk_path(&self, key: &str) -> Option<PathBuf> {
+        self.disk_dir
+            .as_ref()
+            .map(|dir| dir.join(format!("{:016x}.html", fnv1a(key))))
+    }
+
+    async fn read_from_disk(&self, key: &str) -> Option<CacheEntry> {
+        let path = self.disk_path(key)?;
+        let contents = tokio::fs::read_to_string(&path).await.ok()?;
+        let (stored_key, body) = contents.split_once('\n')?;
+        if stored_key != key {
+            return None;
+        }
+        let stored_at = tokio::fs::metadata(&path).await.ok()?.modified().ok()?;
+        Some(CacheEntry {
+            body: body.to_string(),
+            stored_at,
+        })
+    }
+
+    async fn write_to_disk(&self, key: &str, entry: &CacheEntry) {
+        let (Some(dir), Some(path)) = (&self.disk_dir, self.disk_path(key)) else {
+            return;
+        };
+        if let Err(e) = tokio::fs::create_dir_all(dir).await {
+            tracing::warn!("Failed to create cache directory {:?}: {}", dir, e);
+            return;
+        }
+        if let Err(e) = tokio::fs::write(&path, format!("{}\n{}", key, entry.body)).await {
+            tracing::warn!("Failed to write cache entry {:?}: {}", path, e);
+        }
+    }
+
+    async fn remove_from_disk(&self, keys: Vec<String>) {
+        for key in keys {
+            if let Some(path) = self.disk_path(&key) {
+                tokio::fs::remove_file(path).await.ok();
+            }
+        }
+    }
+}
+
+/// Whether `error` means the upstream host is down or unreachable, in which case a
+/// stale copy is better than nothing.
+fn is_host_failure(error: &anyhow::Error) -> bool {
+    error
+        .downcast_ref::<NetworkError>()
+        .is_some_and(NetworkError::is_host_failure)
+}
+
+/// 64-bit FNV-1a, used to turn cache keys into stable file names.
+pub fn fnv1a(input: &str) -> u64 {
+    input.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::test;
+
+    fn fetch_ok(body: &str, calls: &AtomicUsize) -> impl Future<Output = Result<String>> {
+        calls.fetch_add(1, Ordering::SeqCst);
+        let body = body.to_string();
+        async move { Ok(body) }
+    }
+
+    #[test]
+    async fn test_cache_hit_skips_fetch() {
+        let cache = PageCache::new(Duration::from_secs(60), 1024, None);
+        let calls = AtomicUsize::new(0);
+
+        let first = cache
+            .get_or_fetch("a", false, || fetch_ok("one", &calls))
+            .await;
+        let second = cache
+            .get_or_fetch("a", false, || fetch_ok("two", &calls))
+            .await;
+
+        assert_eq!(first.unwrap(), "one");
+        assert_eq!(second.unwrap(), "one");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    async fn test_bypass_refetches_and_updates() {
+        let cache = PageCache::new(Duration::from_secs(60), 1024, None);
+        let calls = AtomicUsize::new(0);
+
+        cache
+            .get_or_fetch("a", false, || fetch_ok("one", &calls))
+            .await
+            .unwrap();
+        let bypassed = cache
+            .get_or_fetch("a", true, || fetch_ok("two", &calls))
+            .await;
+        let cached = cache
+            .get_or_fetch("a", false, || fetch_ok("three", &calls))
+            .await;
+
+        assert_eq!(bypassed.unwrap(), "two");
+        assert_eq!(cached.unwrap(), "two");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    async fn host_down() -> Result<String> {
+        Err(NetworkError::Timeout {
+            url: "https://example.com".to_string(),
+        }
+        .into())
+    }
+
+    #[test]
+    async fn test_stale_copy_served_on_host_failure() {
+        let cache = PageCache::new(Duration::from_millis(1), 1024, None);
+        let calls = AtomicUsize::new(0);
+
+        cache
+            .get_or_fetch("a", false, || fetch_ok("one", &calls))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let stale = cache.get_or_fetch("a", false, host_down).await;
+        let missing = cache.get_or_fetch("b", false, host_down).await;
+
+        assert_eq!(stale.unwrap(), "one");
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    async fn test_stale_copy_not_served_for_missing_page() {
+        let cache = PageCache::new(Duration::from_millis(1), 1024, None);
+        let calls = AtomicUsize::new(0);
+
+        cache
+            .get_or_fetch("a", false, || fetch_ok("one", &calls))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        let gone = cache
+            .get_or_fetch("a", false, || async {
+                Err(NetworkError::Status {
+                    url: "https://example.com/md5/a".to_string(),
+                    code: 404,
+                }
+                .into())
+            })
+            .await;
+        let parse_error = cache
+            .get_or_fetch("a", false, || async { Err(anyhow!("unexpected page")) })
+            .await;
+
+        assert!(gone.is_err());
+        assert!(parse_error.is_err());
+    }
+
+    #[test]
+    async fn test_least_recently_used_entry_evicted() {
+        // Room for two of the two-byte entries below
+        let cache = PageCache::new(Duration::from_secs(60), 4, None);
+        let calls = AtomicUsize::new(0);
+
+        cache
+            .get_or_fetch("a", false, || fetch_ok("a", &calls))
+            .await
+            .unwrap();
+        cache
+            .get_or_fetch("b", false, || fetch_ok("b", &calls))
+            .await
+            .unwrap();
+        // Use "a" again so that "b" becomes the least recently used entry
+        cache
+            .get_or_fetch("a", false, || fetch_ok("a", &calls))
+            .await
+            .unwrap();
+        cache
+            .get_or_fetch("c", false, || fetch_ok("c", &calls))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+
+        cache
+            .get_or_fetch("a", false, || fetch_ok("a", &calls))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        cache
+            .get_or_fetch("b", false, || fetch_ok("b", &calls))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    async fn test_entry_larger_than_cache_not_kept() {
+        let cache = PageCache::new(Duration::from_secs(60), 4, None);
+        let calls = AtomicUsize::new(0);
+
+        for _ in 0..2 {
+            cache
+                .get_or_fetch("a", false, || fetch_ok("too long", &calls))
+                .await
+                .unwrap();
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    async fn test_disk_entries_from_earlier_runs_count_towards_the_size() {
+        let dir = std::env::temp_dir().join(format!("cwa-page-cache-size-{}", std::process::id()));
+        let calls = AtomicUsize::new(0);
+
+        let cache = PageCache::new(Duration::from_secs(60), 1024, Some(dir.clone()));
+        let epoch = SystemTime::now() - Duration::from_secs(60);
+        for (age, key) in ["a", "b", "c"].into_iter().enumerate() {
+            cache
+                .get_or_fetch(key, false, || fetch_ok("page", &calls))
+                .await
+                .unwrap();
+            // Make the write order unambiguous
+            let file = std::fs::File::options()
+                .write(true)
+                .open(cache.disk_path(key).unwrap())
+                .unwrap();
+            file.set_modified(epoch + Duration::from_secs(age as u64))
+                .unwrap();
+        }
+
+        // Room for two of the five-byte entries: the oldest one is dropped from disk
+        let reloaded = PageCache::new(Duration::from_secs(3600), 10, Some(dir.clone()));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        reloaded
+            .get_or_fetch("c", false, || fetch_ok("new", &calls))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        reloaded
+            .get_or_fetch("a", false, || fetch_ok("new", &calls))
+            .await
+            .unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[test]
+    async fn test_disk_cache_survives_new_instance() {
+        let dir = std::env::temp_dir().join(format!("cwa-page-cache-test-{}", std::process::id()));
+        let calls = AtomicUsize::new(0);
+
+        let cache = PageCache::new(Duration::from_secs(60), 1024, Some(dir.clone()));
+        cache
+            .get_or_fetch("https://example.com/md5/abc", false, || {
+                fetch_ok("page", &calls)
+            })
+            .await
+            .unwrap();
+
+        let reloaded = PageCache::new(Duration::from_secs(60), 1024, Some(dir.clone()));
+        let body = reloaded
+            .get_or_fetch("https://example.com/md5/abc", false, || {
+                fetch_ok("new", &calls)
+            })
+            .await;
+
+        assert_eq!(body.unwrap(), "page");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        tokio::fs::remove_dir_all(dir).await.unwrap();
+    }
+}
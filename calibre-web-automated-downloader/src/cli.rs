@@ -0,0 +1,124 @@
+use crate::config::{LoadOptions, SETTINGS};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+Usage: calibre-web-automated-downloader [OPTIONS] [config dump]
+
+Commands:
+  config dump          Print the effective settings and where each came from
+
+Options:
+  --config <PATH>      Read settings from this TOML or YAML file
+  --<setting> <VALUE>  Override any setting, e.g. --max-retry 5 or --flask-port=8084
+  -h, --help           Print this help";
+
+/// What the binary was asked to do.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Serve,
+    ConfigDump,
+    Help,
+}
+
+/// The parsed command line.
+#[derive(Debug, Clone)]
+pub struct Cli {
+    pub command: Command,
+    pub options: LoadOptions,
+}
+
+/// Parse the arguments after the program name.
+///
+/// Every setting can be passed as a flag named after it in kebab case, with the
+/// value either as the next argument or after `=`.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Cli, String> {
+    let mut options = LoadOptions::default();
+    let mut positional = Vec::new();
+    let mut help = false;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            help = true;
+            continue;
+        }
+        let Some(flag) = arg.strip_prefix("--") else {
+            positional.push(arg);
+            continue;
+        };
+
+        let (flag, inline_value) = match flag.split_once('=') {
+            Some((flag, value)) => (flag, Some(value.to_string())),
+            None => (flag, None),
+        };
+        let mut value = || {
+            inline_value
+                .clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("Missing value for --{}", flag))
+        };
+
+        if flag == "config" {
+            options.config_path = Some(PathBuf::from(value()?));
+            continue;
+        }
+        let name = flag.to_uppercase().replace('-', "_");
+        if !SETTINGS.contains(&name.as_str()) {
+            return Err(format!("Unknown option --{}", flag));
+        }
+        options.overrides.insert(name, value()?);
+    }
+
+    let command = if help {
+        Command::Help
+    } else {
+        match positional.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+            [] => Command::Serve,
+            ["config", "dump"] => Command::ConfigDump,
+            _ => return Err(format!("Unknown command '{}'", positional.join(" "))),
+        }
+    };
+    Ok(Cli { command, options })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Cli, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_setting_flags() {
+        let cli = parse_args(&[
+            "--config",
+            "deploy.toml",
+            "--max-retry",
+            "5",
+            "--flask-port=8084",
+        ])
+        .unwrap();
+
+        assert_eq!(cli.command, Command::Serve);
+        assert_eq!(cli.options.config_path, Some(PathBuf::from("deploy.toml")));
+        assert_eq!(cli.options.overrides["MAX_RETRY"], "5");
+        assert_eq!(cli.options.overrides["FLASK_PORT"], "8084");
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(
+            parse_args(&["config", "dump"]).unwrap().command,
+            Command::ConfigDump
+        );
+        assert_eq!(parse_args(&["--help"]).unwrap().command, Command::Help);
+        assert!(parse_args(&["config"]).is_err());
+    }
+
+    #[test]
+    fn test_invalid_flags() {
+        assert!(parse_args(&["--not-a-setting", "1"]).is_err());
+        assert!(parse_args(&["--max-retry"]).is_err());
+    }
+}
@@ -0,0 +1,420 @@
+use crate::cache::fnv1a;
+use crate::config::Config;
+use crate::models::BookInfo;
+use crate::network::{self, redact_url, without_credentials};
+use crate::state::AppState;
+use anyhow::{Context, Result};
+use image::{ImageFormat, ImageReader};
+use serde::Deserialize;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::Cursor;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use url::Url;
+use urlencoding::encode;
+
+/// Thumbnails fit in this box; smaller covers are served as they are.
+const THUMBNAIL_WIDTH: u32 = 200;
+const THUMBNAIL_HEIGHT: u32 = 300;
+/// How many books' cover URLs are remembered for `/api/cover`.
+const MAX_KNOWN: usize = 10_000;
+
+/// The variant of a cover requested from `/api/cover`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoverSize {
+    #[default]
+    Full,
+    Thumbnail,
+}
+
+/// A cover image and its MIME type.
+#[derive(Debug, Clone)]
+pub struct Cover {
+    pub data: Vec<u8>,
+    pub content_type: &'static str,
+}
+
+impl Cover {
+    /// `data` as a cover, if it is an image format we can decode.
+    fn from_data(data: Vec<u8>) -> Option<Self> {
+        let format = image::guess_format(&data).ok()?;
+        format.reading_enabled().then(|| Cover {
+            content_type: format.to_mime_type(),
+            data,
+        })
+    }
+}
+
+/// A cover URL that answered with something other than an image.
+#[derive(Debug)]
+pub struct NotAnImage {
+    url: String,
+}
+
+impl fmt::Display for NotAnImage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} did not return an image", self.url)
+    }
+}
+
+impl std::error::Error for NotAnImage {}
+
+/// Upstream cover URLs by book id, as last seen in search results or book pages.
+#[derive(Debug, Default)]
+struct KnownCovers {
+    urls: HashMap<String, String>,
+    order: VecDeque<String>,
+}
+
+/// The **data** behind the disk cache, guarded by the `Mutex` in `CoverCache`.
+#[derive(Debug, Default)]
+struct CoverCacheData {
+    /// Size of every cached file, by file name.
+    sizes: HashMap<String, u64>,
+    /// File names from least to most recently used.
+    order: VecDeque<String>,
+    total: u64,
+}
+
+/// Cover images fetched through the network layer, so browsers never contact the
+/// cover hosts themselves.
+///
+/// Covers and their thumbnails are kept as files in `dir` until they take up more
+/// than `max_bytes`, after which the least recently used ones are removed.
+#[derive(Debug)]
+pub struct CoverCache {
+    known: Mutex<KnownCovers>,
+    data: Mutex<CoverCacheData>,
+    dir: PathBuf,
+    /// Size budget of `dir`; 0 disables the disk cache.
+    max_bytes: u64,
+}
+
+impl CoverCache {
+    /// A cache in `dir`, picking up the files a previous run left there.
+    pub fn new(dir: PathBuf, max_bytes: u64) -> Self {
+        let mut files = std::fs::read_dir(&dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let entry = entry.ok()?;
+                let metadata = entry.metadata().ok()?;
+                let name = entry.file_name().into_string().ok()?;
+                Some((metadata.modified().ok()?, name, metadata.len()))
+            })
+            .collect::<Vec<_>>();
+        files.sort();
+
+        let mut data = CoverCacheData::default();
+        for (_, name, size) in files {
+            data.total += size;
+            data.sizes.insert(name.clone(), size);
+            data.order.push_back(name);
+        }
+        let cache = CoverCache {
+            known: Mutex::new(KnownCovers::default()),
+            data: Mutex::new(data),
+            dir,
+            max_bytes,
+        };
+        let evicted = cache.evict_internal(&mut cache.data.lock().unwrap());
+        for name in evicted {
+            std::fs::remove_file(cache.dir.join(name)).ok();
+        }
+        cache
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        CoverCache::new(
+            config.tmp_dir.join("covers"),
+            config.cover_cache_size * 1024 * 1024,
+        )
+    }
+
+    /// The upstream cover URL last seen for `book_id`.
+    pub fn url_of(&self, book_id: &str) -> Option<String> {
+        self.known.lock().unwrap().urls.get(book_id).cloned()
+    }
+
+    /// Point `book.preview` at `/api/cover`, remembering the URL it replaces.
+    pub fn proxy(&self, url_base: &str, book: &mut BookInfo, size: CoverSize) {
+        let proxied = book
+            .preview
+            .as_deref()
+            .and_then(|url| self.proxy_url(url_base, &book.id, url, size));
+        if proxied.is_some() {
+            book.preview = proxied;
+        }
+    }
+
+    /// The `/api/cover` URL serving the cover at `url` for `book_id`, remembering
+    /// `url`. `None` for URLs other than http(s) ones, which are not proxied.
+    pub fn proxy_url(
+        &self,
+        url_base: &str,
+        book_id: &str,
+        url: &str,
+        size: CoverSize,
+    ) -> Option<String> {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return None;
+        }
+
+        let mut known = self.known.lock().unwrap();
+        if known
+            .urls
+            .insert(book_id.to_string(), url.to_string())
+            .is_none()
+        {
+            known.order.push_back(book_id.to_string());
+        }
+        while known.order.len() > MAX_KNOWN {
+            if let Some(id) = known.order.pop_front() {
+                known.urls.remove(&id);
+            }
+        }
+
+        let mut proxied = format!("{}/api/cover?id={}", url_base, encode(book_id));
+        if size == CoverSize::Thumbnail {
+            proxied.push_str("&size=thumbnail");
+        }
+        Some(proxied)
+    }
+
+    /// The cover at `url`, from disk if it was fetched before.
+    ///
+    /// Covers are keyed without the user info in `url`, so credentials never end up
+    /// in the cache.
+    pub async fn get(&self, state: &AppState, url: &str, size: CoverSize) -> Result<Cover> {
+        let plain = Url::parse(url)
+            .map(|parsed| without_credentials(&parsed).to_string())
+            .unwrap_or_else(|_| url.to_string());
+        let key = format!("{:016x}", fnv1a(&plain));
+        let original = self.get_or_fetch(&key, || async {
+            let data = network::download_url(state, url).await?;
+            Cover::from_data(data.to_vec()).ok_or_else(|| {
+                NotAnImage {
+                    url: redact_url(url),
+                }
+                .into()
+            })
+        });
+        match size {
+            CoverSize::Full => original.await,
+            CoverSize::Thumbnail => {
+                self.get_or_fetch(&format!("{}-thumbnail", key), || async move {
+                    let original = original.await?;
+                    tokio::task::spawn_blocking(move || thumbnail(original)).await?
+                })
+                .await
+            }
+        }
+    }
+
+    async fn get_or_fetch<F, Fut>(&self, name: &str, fetch: F) -> Result<Cover>
+    where
+        F: FnOnce() -> Fut,
+        Fut: std::future::Future<Output = Result<Cover>>,
+    {
+        if let Some(cover) = self.read(name).await {
+            return Ok(cover);
+        }
+        let cover = fetch().await?;
+        self.write(name, &cover.data).await;
+        Ok(cover)
+    }
+
+    async fn read(&self, name: &str) -> Option<Cover> {
+        if !self.data.lock().unwrap().sizes.contains_key(name) {
+            return None;
+        }
+        let data = tokio::fs::read(self.dir.join(name)).await.ok()?;
+        let mut cache = self.data.lock().unwrap();
+        cache.order.retain(|n| n != name);
+        cache.order.push_back(name.to_string());
+        drop(cache);
+        Cover::from_data(data)
+    }
+
+    /// Store `data` as `name`, removing the least recently used files if the cache
+    /// grows past its budget.
+    async fn write(&self, name: &str, data: &[u8]) {
+        let size = data.len() as u64;
+        if size > self.max_bytes {
+            return;
+        }
+        if let Err(e) = tokio::fs::create_dir_all(&self.dir).await {
+            tracing::warn!("Failed to create cover cache {:?}: {}", self.dir, e);
+            return;
+        }
+        if let Err(e) = tokio::fs::write(self.dir.join(name), data).await {
+            tracing::warn!("Failed to cache cover {}: {}", name, e);
+            return;
+        }
+
+        let evicted = {
+            let mut cache = self.data.lock().unwrap();
+            if let Some(old) = cache.sizes.insert(name.to_string(), size) {
+                cache.total -= old;
+                cache.order.retain(|n| n != name);
+            }
+            cache.total += size;
+            cache.order.push_back(name.to_string());
+            self.evict_internal(&mut cache)
+        };
+        for name in evicted {
+            tokio::fs::remove_file(self.dir.join(name)).await.ok();
+        }
+    }
+
+    /// Drop least recently used files until the cache fits; returns their names.
+    fn evict_internal(&self, data: &mut CoverCacheData) -> Vec<String> {
+        let mut evicted = Vec::new();
+        while data.total > self.max_bytes {
+            let Some(name) = data.order.pop_front() else {
+                break;
+            };
+            data.total -= data.sizes.remove(&name).unwrap_or_default();
+            evicted.push(name);
+        }
+        evicted
+    }
+}
+
+/// `cover` scaled down to fit `THUMBNAIL_WIDTH` x `THUMBNAIL_HEIGHT`, as a JPEG.
+fn thumbnail(cover: Cover) -> Result<Cover> {
+    let image = ImageReader::new(Cursor::new(&cover.data))
+        .with_guessed_format()?
+        .decode()
+        .context("Failed to decode cover")?;
+    if image.width() <= THUMBNAIL_WIDTH && image.height() <= THUMBNAIL_HEIGHT {
+        return Ok(cover);
+    }
+    let mut data = Cursor::new(Vec::new());
+    image
+        .thumbnail(THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT)
+        .into_rgb8()
+        .write_to(&mut data, ImageFormat::Jpeg)?;
+    Ok(Cover {
+        data: data.into_inner(),
+        content_type: "image/jpeg",
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_state;
+    use image::{DynamicImage, GenericImageView, RgbImage};
+    use tokio::test;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        DynamicImage::ImageRgb8(RgbImage::new(width, height))
+            .write_to(&mut data, ImageFormat::Png)
+            .unwrap();
+        data.into_inner()
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("cwa-covers-{}-{}", name, std::process::id()))
+    }
+
+    #[test]
+    async fn test_covers_are_fetched_once_and_resized() {
+        let state = test_state(&[]);
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/cover.png"))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(png(400, 600)))
+            .expect(1)
+            .mount(&server)
+            .await;
+        let url = format!("{}/cover.png", server.uri());
+        let covers = CoverCache::new(temp_dir("resize"), 1024 * 1024);
+
+        let full = covers.get(&state, &url, CoverSize::Full).await.unwrap();
+        assert_eq!(full.content_type, "image/png");
+        assert_eq!(full.data, png(400, 600));
+
+        let thumbnail = covers
+            .get(&state, &url, CoverSize::Thumbnail)
+            .await
+            .unwrap();
+        assert_eq!(thumbnail.content_type, "image/jpeg");
+        let image = image::load_from_memory(&thumbnail.data).unwrap();
+        assert_eq!(image.dimensions(), (THUMBNAIL_WIDTH, THUMBNAIL_HEIGHT));
+
+        // Both now come from disk, also for a new instance
+        let reloaded = CoverCache::new(temp_dir("resize"), 1024 * 1024);
+        let again = reloaded.get(&state, &url, CoverSize::Thumbnail).await;
+        assert_eq!(again.unwrap().data, thumbnail.data);
+        std::fs::remove_dir_all(temp_dir("resize")).unwrap();
+    }
+
+    #[test]
+    async fn test_pages_are_not_proxied_as_covers() {
+        let state = test_state(&[]);
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("<html></html>"))
+            .mount(&server)
+            .await;
+        let covers = CoverCache::new(temp_dir("page"), 1024 * 1024);
+
+        let error = covers
+            .get(&state, &server.uri(), CoverSize::Full)
+            .await
+            .unwrap_err();
+        assert!(error.downcast_ref::<NotAnImage>().is_some());
+    }
+
+    #[test]
+    async fn test_least_recently_used_covers_are_evicted() {
+        let dir = temp_dir("evict");
+        let cover = png(10, 10);
+        let size = cover.len() as u64;
+        let covers = CoverCache::new(dir.clone(), 2 * size);
+
+        covers.write("a", &cover).await;
+        covers.write("b", &cover).await;
+        assert!(covers.read("a").await.is_some());
+        covers.write("c", &cover).await;
+
+        assert!(dir.join("a").exists());
+        assert!(!dir.join("b").exists());
+        assert!(dir.join("c").exists());
+        assert!(covers.read("b").await.is_none());
+
+        // A smaller budget on restart evicts the oldest files straight away
+        let reloaded = CoverCache::new(dir.clone(), size);
+        assert_eq!(reloaded.data.lock().unwrap().sizes.len(), 1);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    async fn test_proxy_rewrites_preview() {
+        let covers = CoverCache::new(temp_dir("proxy"), 0);
+        let mut book = BookInfo::new("opds:urn:1", "Dune");
+        book.preview = Some("https://covers.example/dune.jpg".to_string());
+
+        covers.proxy("/books", &mut book, CoverSize::Thumbnail);
+        assert_eq!(
+            book.preview.as_deref(),
+            Some("/books/api/cover?id=opds%3Aurn%3A1&size=thumbnail")
+        );
+        assert_eq!(
+            covers.url_of("opds:urn:1").as_deref(),
+            Some("https://covers.example/dune.jpg")
+        );
+
+        let mut local = BookInfo::new("a1", "Local");
+        local.preview = Some("/static/cover.jpg".to_string());
+        covers.proxy("", &mut local, CoverSize::Full);
+        assert_eq!(local.preview.as_deref(), Some("/static/cover.jpg"));
+    }
+}
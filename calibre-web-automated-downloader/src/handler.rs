@@ -1,23 +1,225 @@
 use crate::app::AppError;
+use crate::book_manager;
+use crate::config::ConfigView;
+use crate::covers::CoverSize;
+use crate::languages::{Language, LANGUAGES};
+use crate::library::Duplicate;
+use crate::limiter::HostStatus;
+use crate::mirrors::MirrorPoolStatus;
+use crate::models::{BookInfo, QueueStatus};
+use crate::search::{SearchFilters, SearchQuery, SearchResults};
+use crate::state::AppState;
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
 use axum::Json;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
-// How to take a query parameter for search term
-pub async fn handler_search() -> Result<Json<String>, AppError> {
-    Ok(Json("{}".to_string()))
+/// Query parameters of `/search`.
+#[derive(Debug, Deserialize)]
+pub struct SearchParams {
+    #[serde(default)]
+    pub query: String,
+    /// Skip the page cache and always ask the sources.
+    #[serde(default)]
+    pub nocache: bool,
+    /// Filters overriding the configured formats and languages.
+    #[serde(flatten)]
+    pub filters: SearchFilters,
 }
 
-// How to take a query parameter for MD5 id of the book
-pub async fn handler_info() -> Result<Json<String>, AppError> {
-    Ok(Json("{}".to_string()))
+/// Query parameters of the endpoints addressing a single book by its id.
+#[derive(Debug, Deserialize)]
+pub struct BookIdParams {
+    pub id: String,
+    /// Skip the page cache and always ask the sources.
+    #[serde(default)]
+    pub nocache: bool,
 }
 
-pub async fn handler_download() -> Result<Json<String>, AppError> {
-    Ok(Json("{}".to_string()))
+/// Query parameters of `/download`.
+#[derive(Debug, Deserialize)]
+pub struct DownloadParams {
+    pub id: String,
+    /// Skip the page cache and always ask the sources.
+    #[serde(default)]
+    pub nocache: bool,
+    /// Queue the book even if it is already in the library.
+    #[serde(default)]
+    pub force: bool,
 }
 
-pub async fn handler_status() -> Result<Json<String>, AppError> {
-    log::info!("Status request received");
-    Ok(Json("{}".to_string()))
+/// Query parameters of `/cover`.
+#[derive(Debug, Deserialize)]
+pub struct CoverParams {
+    pub id: String,
+    #[serde(default)]
+    pub size: CoverSize,
+}
+
+/// A language searches can be filtered by, and whether `BOOK_LANGUAGE` selects it.
+#[derive(Debug, Serialize)]
+pub struct LanguageOption {
+    #[serde(flatten)]
+    pub language: Language,
+    pub selected: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DownloadResponse {
+    pub status: QueueStatus,
+    /// The copy already in the library, if the book was queued regardless.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate: Option<Duplicate>,
+}
+
+pub async fn handler_search(
+    State(state): State<AppState>,
+    Query(params): Query<SearchParams>,
+) -> Result<Json<SearchResults>, AppError> {
+    let query = SearchQuery::from_filters(&params.query, &params.filters)?;
+    if query.text.trim().is_empty() {
+        return Ok(Json(SearchResults {
+            page: query.page,
+            page_size: query.page_size,
+            ..Default::default()
+        }));
+    }
+    let mut results = state.sources.search(&state, &query, params.nocache).await?;
+    let url_base = &state.config().url_base;
+    for book in &mut results.results {
+        state.covers.proxy(url_base, book, CoverSize::Thumbnail);
+    }
+    state.library.flag(&state, &mut results.results).await;
+    Ok(Json(results))
+}
+
+pub async fn handler_info(
+    State(state): State<AppState>,
+    Query(params): Query<BookIdParams>,
+) -> Result<Json<BookInfo>, AppError> {
+    let mut book = state
+        .sources
+        .get_info(&state, &params.id, params.nocache)
+        .await?;
+    state
+        .covers
+        .proxy(&state.config().url_base, &mut book, CoverSize::Full);
+    state
+        .library
+        .flag(&state, std::slice::from_mut(&mut book))
+        .await;
+    Ok(Json(book))
+}
+
+/// Serves a book's cover through the cover cache, so browsers do not load it from
+/// third-party hosts.
+pub async fn handler_cover(
+    State(state): State<AppState>,
+    Query(params): Query<CoverParams>,
+) -> Result<Response, AppError> {
+    let url = match state.covers.url_of(&params.id) {
+        Some(url) => Some(url),
+        None => match state.queue.get_book(&params.id) {
+            Some(book) => book.preview,
+            None => {
+                state
+                    .sources
+                    .get_info(&state, &params.id, false)
+                    .await?
+                    .preview
+            }
+        },
+    };
+    let Some(url) = url else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+    let url = state.sources.authorize_url(&params.id, &url);
+    let cover = state.covers.get(&state, &url, params.size).await?;
+    Ok((
+        [
+            (header::CONTENT_TYPE, cover.content_type),
+            (header::CACHE_CONTROL, "public, max-age=86400"),
+        ],
+        cover.data,
+    )
+        .into_response())
+}
+
+pub async fn handler_download(
+    State(state): State<AppState>,
+    Query(params): Query<DownloadParams>,
+) -> Result<Json<DownloadResponse>, AppError> {
+    let book = state
+        .sources
+        .get_info(&state, &params.id, params.nocache)
+        .await?;
+    let duplicate = state
+        .library
+        .check_download(&state, &book, params.force)
+        .await?;
+    if let Some(duplicate) = &duplicate {
+        tracing::warn!(
+            "Queueing {}, which is already owned: {:?}",
+            params.id,
+            duplicate
+        );
+    }
+    book_manager::queue_book(&state, &params.id, book);
+    Ok(Json(DownloadResponse {
+        status: QueueStatus::Queued,
+        duplicate,
+    }))
+}
+
+pub async fn handler_status(
+    State(state): State<AppState>,
+) -> Result<Json<HashMap<QueueStatus, HashMap<String, BookInfo>>>, AppError> {
+    tracing::info!("Status request received");
+    let mut status = book_manager::get_queue_status(&state);
+    let url_base = &state.config().url_base;
+    for book in status.values_mut().flat_map(|books| books.values_mut()) {
+        state.covers.proxy(url_base, book, CoverSize::Thumbnail);
+    }
+    Ok(Json(status))
+}
+
+/// Reports the active Anna's Archive mirror and the health of every configured mirror.
+pub async fn handler_mirrors(
+    State(state): State<AppState>,
+) -> Result<Json<MirrorPoolStatus>, AppError> {
+    Ok(Json(state.mirrors.status()))
+}
+
+/// Reports the rate limiter and circuit breaker state of every host contacted so far.
+pub async fn handler_admin_hosts(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<HostStatus>>, AppError> {
+    Ok(Json(state.limiter.status()))
+}
+
+/// Reports the effective configuration with secrets masked.
+pub async fn handler_config(State(state): State<AppState>) -> Result<Json<ConfigView>, AppError> {
+    Ok(Json(state.config().view()))
+}
+
+/// Lists the supported languages, for building a language picker.
+pub async fn handler_languages(
+    State(state): State<AppState>,
+) -> Result<Json<Vec<LanguageOption>>, AppError> {
+    let config = state.config();
+    let languages = LANGUAGES
+        .iter()
+        .map(|language| LanguageOption {
+            language: *language,
+            selected: config
+                .book_language
+                .iter()
+                .any(|code| code == language.code),
+        })
+        .collect();
+    Ok(Json(languages))
 }
 
 pub async fn handler_localdownload() -> Result<Json<String>, AppError> {
@@ -0,0 +1,320 @@
+use serde::Serialize;
+use std::fmt;
+
+/// A language Anna's Archive can filter search results by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Language {
+    /// The tag Anna's Archive expects in its `lang` parameter.
+    pub code: &'static str,
+    /// English name, for display.
+    pub name: &'static str,
+}
+
+const fn language(code: &'static str, name: &'static str) -> Language {
+    Language { code, name }
+}
+
+/// Languages supported by Anna's Archive search, in the order its filter lists them.
+pub static LANGUAGES: &[Language] = &[
+    language("en", "English"),
+    language("zh", "Chinese"),
+    language("ru", "Russian"),
+    language("es", "Spanish"),
+    language("fr", "French"),
+    language("de", "German"),
+    language("it", "Italian"),
+    language("pt", "Portuguese"),
+    language("pl", "Polish"),
+    language("bg", "Bulgarian"),
+    language("nl", "Dutch"),
+    language("ja", "Japanese"),
+    language("ar", "Arabic"),
+    language("he", "Hebrew"),
+    language("hu", "Hungarian"),
+    language("la", "Latin"),
+    language("cs", "Czech"),
+    language("ko", "Korean"),
+    language("tr", "Turkish"),
+    language("uk", "Ukrainian"),
+    language("id", "Indonesian"),
+    language("ro", "Romanian"),
+    language("el", "Greek"),
+    language("lt", "Lithuanian"),
+    language("bn", "Bengali"),
+    language("zh-Hant", "Chinese (Traditional)"),
+    language("af", "Afrikaans"),
+    language("ca", "Catalan"),
+    language("sv", "Swedish"),
+    language("th", "Thai"),
+    language("hi", "Hindi"),
+    language("ga", "Irish"),
+    language("lv", "Latvian"),
+    language("kn", "Kannada"),
+    language("sr", "Serbian"),
+    language("bo", "Tibetan"),
+    language("da", "Danish"),
+    language("fa", "Persian"),
+    language("hr", "Croatian"),
+    language("sk", "Slovak"),
+    language("jv", "Javanese"),
+    language("vi", "Vietnamese"),
+    language("ur", "Urdu"),
+    language("fi", "Finnish"),
+    language("no", "Norwegian"),
+    language("rw", "Kinyarwanda"),
+    language("ta", "Tamil"),
+    language("be", "Belarusian"),
+    language("kk", "Kazakh"),
+    language("mn", "Mongolian"),
+    language("ka", "Georgian"),
+    language("sl", "Slovenian"),
+    language("eo", "Esperanto"),
+    language("gl", "Galician"),
+    language("mr", "Marathi"),
+    language("fil", "Filipino"),
+    language("gu", "Gujarati"),
+    language("ml", "Malayalam"),
+    language("ky", "Kyrgyz"),
+    language("qu", "Quechua"),
+    language("az", "Azerbaijani"),
+    language("sw", "Swahili"),
+    language("ba", "Bashkir"),
+    language("pa", "Punjabi"),
+    language("ms", "Malay"),
+    language("te", "Telugu"),
+    language("sq", "Albanian"),
+    language("ug", "Uyghur"),
+    language("hy", "Armenian"),
+    language("shn", "Shan"),
+];
+
+/// Names and codes that mean one of the [`LANGUAGES`], besides their English name.
+///
+/// Keys are lowercase with `-` between words.
+static ALIASES: &[(&str, &str)] = &[
+    ("chinese-traditional", "zh-Hant"),
+    ("traditional-chinese", "zh-Hant"),
+    ("chinese-simplified", "zh"),
+    ("simplified-chinese", "zh"),
+    ("mandarin", "zh"),
+    ("farsi", "fa"),
+    ("tagalog", "fil"),
+    ("bokmal", "no"),
+    ("nynorsk", "no"),
+    ("castilian", "es"),
+    ("flemish", "nl"),
+    ("uighur", "ug"),
+    ("kirghiz", "ky"),
+    ("panjabi", "pa"),
+    // ISO 639-2 codes
+    ("eng", "en"),
+    ("chi", "zh"),
+    ("zho", "zh"),
+    ("rus", "ru"),
+    ("spa", "es"),
+    ("fre", "fr"),
+    ("fra", "fr"),
+    ("ger", "de"),
+    ("deu", "de"),
+    ("ita", "it"),
+    ("por", "pt"),
+    ("pol", "pl"),
+    ("dut", "nl"),
+    ("nld", "nl"),
+    ("jpn", "ja"),
+    ("ara", "ar"),
+    ("heb", "he"),
+    ("kor", "ko"),
+    ("tur", "tr"),
+    ("ukr", "uk"),
+    // Codes that were replaced or that share a macrolanguage
+    ("iw", "he"),
+    ("in", "id"),
+    ("tl", "fil"),
+    ("nb", "no"),
+    ("nn", "no"),
+    ("cmn", "zh"),
+];
+
+/// Why a configured language was not used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RejectedLanguage {
+    pub value: String,
+    pub reason: String,
+}
+
+impl fmt::Display for RejectedLanguage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:?}: {}", self.value, self.reason)
+    }
+}
+
+/// Find the language by its code.
+pub fn by_code(code: &str) -> Option<&'static Language> {
+    LANGUAGES.iter().find(|l| l.code.eq_ignore_ascii_case(code))
+}
+
+/// Replace underscores, spaces and the various Unicode hyphens with `-`.
+fn normalize(value: &str) -> String {
+    value
+        .trim()
+        .chars()
+        .map(|c| match c {
+            '_' | ' ' | '\u{2010}'..='\u{2015}' | '\u{2212}' | '\u{FE63}' | '\u{FF0D}' => '-',
+            c => c,
+        })
+        .collect()
+}
+
+fn by_name(value: &str) -> Option<&'static Language> {
+    let key = value.to_lowercase();
+    if let Some(language) = LANGUAGES
+        .iter()
+        .find(|l| l.name.to_lowercase().replace(' ', "-") == key)
+    {
+        return Some(language);
+    }
+    ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == key)
+        .and_then(|(_, code)| by_code(code))
+}
+
+/// Whether `tag` is well-formed BCP-47: a 2-3 letter language subtag followed by
+/// subtags of 1-8 letters or digits.
+fn is_language_tag(tag: &str) -> bool {
+    let mut subtags = tag.split('-');
+    let language = subtags.next().unwrap_or_default();
+    (2..=3).contains(&language.len())
+        && language.chars().all(|c| c.is_ascii_alphabetic())
+        && subtags
+            .all(|s| (1..=8).contains(&s.len()) && s.chars().all(|c| c.is_ascii_alphanumeric()))
+}
+
+/// Resolve a BCP-47 tag, ISO 639 code or English name to a supported language.
+///
+/// Region and other subtags narrow nothing except Chinese, where the Traditional
+/// script or a region writing it (`zh-TW`, `zh-HK`, `zh-MO`) picks `zh-Hant`.
+pub fn parse(value: &str) -> Result<&'static Language, RejectedLanguage> {
+    let reject = |reason: String| RejectedLanguage {
+        value: value.trim().to_string(),
+        reason,
+    };
+    let normalized = normalize(value);
+    if normalized.is_empty() {
+        return Err(reject("empty language".to_string()));
+    }
+    if let Some(language) = by_name(&normalized) {
+        return Ok(language);
+    }
+    if !is_language_tag(&normalized) {
+        return Err(reject(
+            "expected a language code such as `en` or `pt-BR`, or an English name".to_string(),
+        ));
+    }
+
+    let subtags = normalized.to_lowercase();
+    let mut subtags = subtags.split('-');
+    let primary = subtags.next().unwrap_or_default();
+    let rest = subtags.collect::<Vec<_>>();
+    if primary == "zh"
+        && rest
+            .iter()
+            .any(|s| matches!(*s, "hant" | "tw" | "hk" | "mo"))
+    {
+        return Ok(by_code("zh-Hant").expect("zh-Hant is a supported language"));
+    }
+    by_code(primary)
+        .or_else(|| by_name(primary))
+        .ok_or_else(|| {
+            reject(format!(
+                "`{}` is not a language Anna's Archive can filter by",
+                primary
+            ))
+        })
+}
+
+/// Resolve a comma separated list of languages, dropping duplicates.
+pub fn parse_list(value: &str) -> (Vec<&'static Language>, Vec<RejectedLanguage>) {
+    let mut languages = Vec::new();
+    let mut rejected = Vec::new();
+    for entry in value.split(',').filter(|s| !s.trim().is_empty()) {
+        match parse(entry) {
+            Ok(language) if !languages.contains(&language) => languages.push(language),
+            Ok(_) => {}
+            Err(e) => rejected.push(e),
+        }
+    }
+    (languages, rejected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code(value: &str) -> Option<&'static str> {
+        parse(value).ok().map(|l| l.code)
+    }
+
+    #[test]
+    fn test_codes_and_tags() {
+        assert_eq!(code("en"), Some("en"));
+        assert_eq!(code(" DE "), Some("de"));
+        assert_eq!(code("pt-BR"), Some("pt"));
+        assert_eq!(code("en_GB"), Some("en"));
+        assert_eq!(code("fil"), Some("fil"));
+        assert_eq!(code("sr-Latn-RS"), Some("sr"));
+    }
+
+    #[test]
+    fn test_traditional_chinese() {
+        assert_eq!(code("zh-Hant"), Some("zh-Hant"));
+        assert_eq!(code("zh-hant"), Some("zh-Hant"));
+        assert_eq!(code("zh\u{2011}Hant"), Some("zh-Hant"));
+        assert_eq!(code("zh-Hant-TW"), Some("zh-Hant"));
+        assert_eq!(code("zh-TW"), Some("zh-Hant"));
+        assert_eq!(code("zh-Hans"), Some("zh"));
+        assert_eq!(code("zh-CN"), Some("zh"));
+    }
+
+    #[test]
+    fn test_names_and_aliases() {
+        assert_eq!(code("English"), Some("en"));
+        assert_eq!(code("english"), Some("en"));
+        assert_eq!(code("Chinese (Traditional)"), Some("zh-Hant"));
+        assert_eq!(code("chinese-traditional"), Some("zh-Hant"));
+        assert_eq!(code("Chinese Traditional"), Some("zh-Hant"));
+        assert_eq!(code("farsi"), Some("fa"));
+        assert_eq!(code("ger"), Some("de"));
+        assert_eq!(code("iw"), Some("he"));
+    }
+
+    #[test]
+    fn test_rejections() {
+        assert!(parse("xx").unwrap_err().reason.contains("`xx`"));
+        assert!(parse("yi").is_err());
+        assert!(parse("klingon").is_err());
+        assert!(parse("e").is_err());
+        assert!(parse("en-").is_err());
+        assert_eq!(parse("  ").unwrap_err().reason, "empty language");
+    }
+
+    #[test]
+    fn test_parse_list() {
+        let (languages, rejected) = parse_list("en, english,en-US,xx,de,,zh-TW");
+        assert_eq!(
+            languages.iter().map(|l| l.code).collect::<Vec<_>>(),
+            vec!["en", "de", "zh-Hant"]
+        );
+        assert_eq!(rejected.len(), 1);
+        assert_eq!(rejected[0].value, "xx");
+    }
+
+    #[test]
+    fn test_codes_are_unique_and_ascii() {
+        for (i, language) in LANGUAGES.iter().enumerate() {
+            assert!(language.code.is_ascii(), "{}", language.code);
+            assert!(LANGUAGES[..i].iter().all(|l| l.code != language.code));
+        }
+    }
+}
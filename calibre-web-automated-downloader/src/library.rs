@@ -0,0 +1,501 @@
+use crate::metadata::Isbn;
+use crate::models::{BookInfo, QueueStatus};
+use crate::state::AppState;
+use anyhow::Result;
+use rusqlite::{Connection, OpenFlags};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// What `/api/download` does with books that are already in the library.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DuplicateCheck {
+    /// Do not look for duplicates at all.
+    Off,
+    /// Queue them anyway, but say so in the response.
+    #[default]
+    Warn,
+    /// Refuse them unless the request is forced.
+    Refuse,
+}
+
+impl FromStr for DuplicateCheck {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(DuplicateCheck::Off),
+            "warn" => Ok(DuplicateCheck::Warn),
+            "refuse" => Ok(DuplicateCheck::Refuse),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for DuplicateCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            DuplicateCheck::Off => "off",
+            DuplicateCheck::Warn => "warn",
+            DuplicateCheck::Refuse => "refuse",
+        })
+    }
+}
+
+/// Where a copy of a book was found.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Location {
+    /// The Calibre library at `CALIBRE_DB`.
+    Calibre,
+    /// A file downloaded earlier, waiting in the ingest directory.
+    IngestDir,
+}
+
+/// What a book was matched with its copy by, from the strongest to the weakest match.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchedBy {
+    Id,
+    Md5,
+    Isbn,
+    TitleAuthor,
+}
+
+/// A copy of a book that is already owned.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Duplicate {
+    pub location: Location,
+    pub matched_by: MatchedBy,
+    /// The title of the copy, which may differ from the book's.
+    pub title: String,
+}
+
+/// A download refused because the book is already owned.
+#[derive(Debug)]
+pub struct DuplicateBook(pub Duplicate);
+
+impl fmt::Display for DuplicateBook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let location = match self.0.location {
+            Location::Calibre => "the Calibre library",
+            Location::IngestDir => "the ingest directory",
+        };
+        write!(
+            f,
+            "\"{}\" is already in {}; pass force=true to download it anyway",
+            self.0.title, location
+        )
+    }
+}
+
+impl std::error::Error for DuplicateBook {}
+
+/// Lowercase words of `text`, without punctuation.
+fn normalise(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_alphanumeric() { c } else { ' ' })
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The key books with the same title and author share: the title without subtitle
+/// or bracketed notes, and the author's names in any order, so "Herbert, Frank"
+/// matches "Frank Herbert".
+fn title_author_key(title: &str, author: &str) -> Option<String> {
+    let title = title.split([':', '(', '[']).next().unwrap_or_default();
+    let title = normalise(title);
+    let mut names = normalise(author)
+        .split(' ')
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    names.sort();
+    let author = names.join(" ");
+    (!title.is_empty() && !author.is_empty()).then(|| format!("{}|{}", title, author))
+}
+
+/// Books already owned, by the keys duplicates are matched on. Values are titles.
+#[derive(Debug, Default)]
+struct LibraryIndex {
+    md5s: HashMap<String, String>,
+    isbns: HashMap<String, String>,
+    titles: HashMap<String, String>,
+}
+
+impl LibraryIndex {
+    fn add_title(&mut self, title: &str, authors: &[String]) {
+        for author in authors {
+            if let Some(key) = title_author_key(title, author) {
+                self.titles.insert(key, title.to_string());
+            }
+        }
+    }
+
+    fn add_book(&mut self, book: &BookInfo) {
+        if let Some(md5) = &book.metadata.identifiers.md5 {
+            self.md5s.insert(md5.to_lowercase(), book.title.clone());
+        }
+        for isbn in &book.metadata.isbns {
+            self.isbns.insert(isbn.isbn_13.clone(), book.title.clone());
+        }
+        self.add_title(&book.title, &book.authors);
+    }
+
+    fn find(&self, book: &BookInfo) -> Option<(MatchedBy, String)> {
+        let md5 = book.metadata.identifiers.md5.as_ref();
+        if let Some(title) = md5.and_then(|md5| self.md5s.get(&md5.to_lowercase())) {
+            return Some((MatchedBy::Md5, title.clone()));
+        }
+        for isbn in &book.metadata.isbns {
+            if let Some(title) = self.isbns.get(&isbn.isbn_13) {
+                return Some((MatchedBy::Isbn, title.clone()));
+            }
+        }
+        book.authors
+            .iter()
+            .filter_map(|author| title_author_key(&book.title, author))
+            .find_map(|key| self.titles.get(&key))
+            .map(|title| (MatchedBy::TitleAuthor, title.clone()))
+    }
+
+    /// Index the books of a Calibre `metadata.db`, opened read-only.
+    fn read_calibre(path: &Path) -> Result<Self> {
+        let db = Connection::open_with_flags(
+            path,
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        let mut titles = HashMap::<i64, String>::new();
+        let mut authors = HashMap::<i64, Vec<String>>::new();
+        let mut index = LibraryIndex::default();
+
+        let mut books = db.prepare("SELECT id, title FROM books")?;
+        for row in books.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (id, title) = row?;
+            titles.insert(id, title);
+        }
+        let mut links = db.prepare(
+            "SELECT books_authors_link.book, authors.name FROM books_authors_link \
+             JOIN authors ON authors.id = books_authors_link.author",
+        )?;
+        for row in links.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (book, name) = row?;
+            authors.entry(book).or_default().push(name);
+        }
+        let mut identifiers =
+            db.prepare("SELECT book, type, val FROM identifiers WHERE type IN ('isbn', 'md5')")?;
+        let rows = identifiers.query_map([], |row| {
+            Ok((
+                row.get::<_, i64>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+            ))
+        })?;
+        for row in rows {
+            let (book, kind, value) = row?;
+            let Some(title) = titles.get(&book) else {
+                continue;
+            };
+            match kind.as_str() {
+                "isbn" => {
+                    if let Some(isbn) = Isbn::parse(&value) {
+                        index.isbns.insert(isbn.isbn_13, title.clone());
+                    }
+                }
+                _ => {
+                    index.md5s.insert(value.to_lowercase(), title.clone());
+                }
+            }
+        }
+        for (id, title) in &titles {
+            index.add_title(title, authors.get(id).map_or(&[], Vec::as_slice));
+        }
+        Ok(index)
+    }
+}
+
+/// The index of a Calibre database, and the database it was read from.
+#[derive(Debug)]
+struct CalibreIndex {
+    path: PathBuf,
+    modified: SystemTime,
+    index: Arc<LibraryIndex>,
+}
+
+/// Looks books up in the Calibre library and the ingest directory, so they are not
+/// downloaded twice.
+///
+/// The Calibre database is read into memory, and read again whenever it changes.
+#[derive(Debug, Default)]
+pub struct Library {
+    calibre: Mutex<Option<CalibreIndex>>,
+}
+
+impl Library {
+    /// The index of `CALIBRE_DB`, if one is configured and readable.
+    async fn calibre_index(&self, state: &AppState) -> Option<Arc<LibraryIndex>> {
+        let path = state.config().calibre_db.clone()?;
+        let modified = match tokio::fs::metadata(&path).await.and_then(|m| m.modified()) {
+            Ok(modified) => modified,
+            Err(e) => {
+                tracing::warn!("Cannot read Calibre database {:?}: {}", path, e);
+                return None;
+            }
+        };
+        if let Some(cached) = &*self.calibre.lock().unwrap() {
+            if cached.path == path && cached.modified == modified {
+                return Some(cached.index.clone());
+            }
+        }
+
+        let read_path = path.clone();
+        let index = tokio::task::spawn_blocking(move || LibraryIndex::read_calibre(&read_path))
+            .await
+            .map_err(anyhow::Error::from)
+            .and_then(|result| result);
+        match index {
+            Ok(index) => {
+                let index = Arc::new(index);
+                tracing::info!(
+                    "Read {} titles from Calibre database {:?}",
+                    index.titles.len(),
+                    path
+                );
+                *self.calibre.lock().unwrap() = Some(CalibreIndex {
+                    path,
+                    modified,
+                    index: index.clone(),
+                });
+                Some(index)
+            }
+            Err(e) => {
+                tracing::warn!("Failed to read Calibre database {:?}: {:#}", path, e);
+                None
+            }
+        }
+    }
+
+    /// Set `duplicate` on every book already owned, unless `DUPLICATE_CHECK` is off.
+    pub async fn flag(&self, state: &AppState, books: &mut [BookInfo]) {
+        if state.config().duplicate_check == DuplicateCheck::Off {
+            return;
+        }
+        let calibre = self.calibre_index(state).await;
+        let ingested = ingest_index(state);
+        for book in books {
+            book.duplicate = find(state, calibre.as_deref(), &ingested, book);
+        }
+    }
+
+    /// Check `book` before it is queued: with `DUPLICATE_CHECK=refuse` a duplicate is
+    /// an error unless `force` is set, otherwise it is returned so it can be reported.
+    pub async fn check_download(
+        &self,
+        state: &AppState,
+        book: &BookInfo,
+        force: bool,
+    ) -> Result<Option<Duplicate>, DuplicateBook> {
+        let mut books = [book.clone()];
+        self.flag(state, &mut books).await;
+        let [book] = books;
+        match book.duplicate {
+            Some(duplicate)
+                if !force && state.config().duplicate_check == DuplicateCheck::Refuse =>
+            {
+                Err(DuplicateBook(duplicate))
+            }
+            duplicate => Ok(duplicate),
+        }
+    }
+}
+
+/// The books whose files are still in the ingest directory.
+fn ingest_index(state: &AppState) -> LibraryIndex {
+    let mut index = LibraryIndex::default();
+    for entry in state.queue.history() {
+        if entry.status == QueueStatus::Available && state.queue.ingest_path(&entry.id).exists() {
+            index.add_book(&entry.book);
+        }
+    }
+    index
+}
+
+fn find(
+    state: &AppState,
+    calibre: Option<&LibraryIndex>,
+    ingested: &LibraryIndex,
+    book: &BookInfo,
+) -> Option<Duplicate> {
+    if let Some((matched_by, title)) = calibre.and_then(|index| index.find(book)) {
+        return Some(Duplicate {
+            location: Location::Calibre,
+            matched_by,
+            title,
+        });
+    }
+
+    // Downloads are named after the book id, which for Anna's Archive is the MD5
+    let md5 = book.metadata.identifiers.md5.as_deref();
+    let by_file = [
+        (MatchedBy::Id, Some(book.id.as_str())),
+        (MatchedBy::Md5, md5),
+    ]
+    .into_iter()
+    .find(|(_, id)| id.is_some_and(|id| state.queue.ingest_path(id).exists()))
+    .map(|(matched_by, _)| (matched_by, book.title.clone()));
+    by_file
+        .or_else(|| ingested.find(book))
+        .map(|(matched_by, title)| Duplicate {
+            location: Location::IngestDir,
+            matched_by,
+            title,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_state;
+    use tokio::test;
+
+    /// A `metadata.db` with the tables Calibre's duplicate lookups use.
+    fn calibre_db(dir: &Path) -> PathBuf {
+        std::fs::create_dir_all(dir).unwrap();
+        let path = dir.join("metadata.db");
+        let db = Connection::open(&path).unwrap();
+        db.execute_batch(
+            "CREATE TABLE books (id INTEGER PRIMARY KEY, title TEXT);
+             CREATE TABLE authors (id INTEGER PRIMARY KEY, name TEXT);
+             CREATE TABLE books_authors_link (id INTEGER PRIMARY KEY, book INTEGER, author INTEGER);
+             CREATE TABLE identifiers (id INTEGER PRIMARY KEY, book INTEGER, type TEXT, val TEXT);
+             INSERT INTO books VALUES (1, 'Dune'), (2, 'The Hobbit'), (3, 'Emma');
+             INSERT INTO authors VALUES (1, 'Frank Herbert'), (2, 'J. R. R. Tolkien');
+             INSERT INTO books_authors_link VALUES (1, 1, 1), (2, 2, 2);
+             INSERT INTO identifiers VALUES
+                 (1, 2, 'isbn', '978-0-261-10221-7'),
+                 (2, 3, 'md5', 'ABCDEF0123456789ABCDEF0123456789');",
+        )
+        .unwrap();
+        path
+    }
+
+    fn book(id: &str, title: &str, author: &str) -> BookInfo {
+        let mut book = BookInfo::new(id, title);
+        book.authors = vec![author.to_string()];
+        book
+    }
+
+    #[test]
+    async fn test_calibre_matches() {
+        let state = test_state(&[]);
+        let dir = state.config().tmp_dir.join("library");
+        let state = test_state(&[("CALIBRE_DB", calibre_db(&dir).to_str().unwrap())]);
+
+        let mut hobbit = book("a1", "Hobbit", "Someone");
+        hobbit.metadata.isbns = Isbn::parse("0261102214").into_iter().collect();
+        let mut emma = book("a2", "Emma", "Jane Austen");
+        emma.metadata.identifiers.md5 = Some("abcdef0123456789abcdef0123456789".to_string());
+        let mut books = [
+            book("a0", "Dune: Deluxe Edition", "Herbert, Frank"),
+            hobbit,
+            emma,
+            book("a3", "Dune Messiah", "Frank Herbert"),
+        ];
+        state.library.flag(&state, &mut books).await;
+
+        let matches = books
+            .iter()
+            .map(|book| {
+                book.duplicate
+                    .as_ref()
+                    .map(|d| (d.matched_by, d.title.as_str()))
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            matches,
+            vec![
+                Some((MatchedBy::TitleAuthor, "Dune")),
+                Some((MatchedBy::Isbn, "The Hobbit")),
+                Some((MatchedBy::Md5, "Emma")),
+                None,
+            ]
+        );
+        assert_eq!(
+            books[0].duplicate.as_ref().unwrap().location,
+            Location::Calibre
+        );
+    }
+
+    #[test]
+    async fn test_ingest_dir_matches() {
+        let state = test_state(&[]);
+        let mut dune = book("opds:1", "Dune", "Frank Herbert");
+        dune.metadata.identifiers.md5 = Some("0123".to_string());
+        state.queue.add("opds:1", dune);
+        state.queue.update_status("opds:1", QueueStatus::Available);
+        std::fs::write(state.queue.ingest_path("opds:1"), "epub").unwrap();
+        std::fs::write(state.queue.ingest_path("4567"), "epub").unwrap();
+
+        let mut from_aa = book("0123", "Dune", "Frank Herbert");
+        from_aa.metadata.identifiers.md5 = Some("0123".to_string());
+        let mut books = [
+            book("opds:1", "Dune", "Frank Herbert"),
+            book("4567", "Children of Dune", "Frank Herbert"),
+            book("b", "dune", "HERBERT, FRANK"),
+            from_aa,
+        ];
+        state.library.flag(&state, &mut books).await;
+
+        let matches = books
+            .iter()
+            .map(|book| book.duplicate.as_ref().map(|d| (d.location, d.matched_by)))
+            .collect::<Vec<_>>();
+        let ingest = Location::IngestDir;
+        assert_eq!(
+            matches,
+            vec![
+                Some((ingest, MatchedBy::Id)),
+                Some((ingest, MatchedBy::Id)),
+                Some((ingest, MatchedBy::TitleAuthor)),
+                Some((ingest, MatchedBy::Md5)),
+            ]
+        );
+    }
+
+    #[test]
+    async fn test_check_download() {
+        let owned = book("a1", "Dune", "Frank Herbert");
+        let new = book("a2", "Emma", "Jane Austen");
+        for (check, refused) in [("refuse", true), ("warn", false), ("off", false)] {
+            let state = test_state(&[("DUPLICATE_CHECK", check)]);
+            state.queue.add("a1", owned.clone());
+            state.queue.update_status("a1", QueueStatus::Available);
+            std::fs::write(state.queue.ingest_path("a1"), "epub").unwrap();
+            let library = &state.library;
+
+            let result = library.check_download(&state, &owned, false).await;
+            assert_eq!(result.is_err(), refused, "{}", check);
+            let forced = library.check_download(&state, &owned, true).await;
+            assert_eq!(forced.unwrap().is_some(), check != "off", "{}", check);
+            assert_eq!(
+                library.check_download(&state, &new, false).await.unwrap(),
+                None
+            );
+        }
+    }
+
+    #[test]
+    async fn test_title_author_key() {
+        assert_eq!(
+            title_author_key("The Hobbit (Illustrated) [EN]", "Tolkien, J.R.R."),
+            Some("the hobbit|j r r tolkien".to_string())
+        );
+        assert_eq!(title_author_key("Dune", ""), None);
+    }
+}
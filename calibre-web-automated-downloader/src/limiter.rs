@@ -0,0 +1,395 @@
+use crate::config::Config;
+use crate::network::NetworkError;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use url::Url;
+
+/// State of the circuit breaker for a single host.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// Requests flow normally.
+    Closed,
+    /// Too many consecutive failures; requests are rejected until the cool-down ends.
+    Open,
+    /// The cool-down has ended and a single probe request is allowed through.
+    HalfOpen,
+}
+
+/// Token bucket and circuit breaker state for a single host.
+#[derive(Debug)]
+struct HostState {
+    tokens: f64,
+    last_refill: Instant,
+    circuit: CircuitState,
+    consecutive_failures: u64,
+    opened_at: Option<Instant>,
+    /// The permit probing a half-open circuit, if one is out.
+    probe_in_flight: Option<u64>,
+}
+
+impl HostState {
+    fn new(burst: f64) -> Self {
+        HostState {
+            tokens: burst,
+            last_refill: Instant::now(),
+            circuit: CircuitState::Closed,
+            consecutive_failures: 0,
+            opened_at: None,
+            probe_in_flight: None,
+        }
+    }
+}
+
+/// Permission to send one request to a host, returned by [`HostLimiter::acquire`].
+///
+/// Hold it until the outcome is recorded. If it was the probe of a half-open circuit
+/// and is dropped without an outcome, e.g. because the request was cancelled, the
+/// next request may probe instead.
+#[derive(Debug)]
+pub struct Permit<'a> {
+    limiter: &'a HostLimiter,
+    host: String,
+    probe: Option<u64>,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        let Some(probe) = self.probe else {
+            return;
+        };
+        let mut hosts = self.limiter.hosts.lock().unwrap();
+        if let Some(state) = hosts.get_mut(&self.host) {
+            if state.probe_in_flight == Some(probe) {
+                tracing::debug!("Probe of host {} ended without an outcome", self.host);
+                state.probe_in_flight = None;
+            }
+        }
+    }
+}
+
+/// Snapshot of a single host, as reported by the admin endpoint.
+#[derive(Clone, Debug, Serialize)]
+pub struct HostStatus {
+    pub host: String,
+    pub circuit: CircuitState,
+    pub consecutive_failures: u64,
+    pub available_tokens: f64,
+    /// Seconds until an open circuit half-opens again.
+    pub retry_in_secs: Option<u64>,
+}
+
+/// Per-host token-bucket rate limiter combined with a circuit breaker.
+///
+/// Every outbound request first calls `acquire` for its host, which waits for a
+/// token and rejects the request outright while the host's circuit is open. The
+/// outcome is then reported back through `record_success` / `record_failure`.
+#[derive(Debug)]
+pub struct HostLimiter {
+    hosts: Mutex<HashMap<String, HostState>>,
+    /// Id of the next half-open probe, so a permit only releases its own probe.
+    next_probe: AtomicU64,
+    /// Tokens added per second; 0 disables rate limiting.
+    rate: f64,
+    burst: f64,
+    /// Consecutive failures before the circuit opens; 0 disables the breaker.
+    failure_threshold: u64,
+    cooldown: Duration,
+}
+
+impl HostLimiter {
+    pub fn new(rate: f64, burst: u64, failure_threshold: u64, cooldown: Duration) -> Self {
+        HostLimiter {
+            hosts: Mutex::new(HashMap::new()),
+            next_probe: AtomicU64::new(0),
+            rate,
+            burst: burst.max(1) as f64,
+            failure_threshold,
+            cooldown,
+        }
+    }
+
+    /// The limiter described by the rate limit and circuit breaker settings of `config`.
+    pub fn from_config(config: &Config) -> Self {
+        HostLimiter::new(
+            config.rate_limit_per_second,
+            config.rate_limit_burst,
+            config.circuit_breaker_threshold,
+            Duration::from_secs(config.circuit_breaker_cooldown),
+        )
+    }
+
+    /// Wait until a request to `host` is allowed.
+    ///
+    /// Returns an error without waiting if the circuit for the host is open, or if it
+    /// is half-open and another request is already probing it. A request only becomes
+    /// the probe once it has its token, so waiting for one never blocks the host.
+    pub async fn acquire(&self, host: &str) -> Result<Permit<'_>, NetworkError> {
+        loop {
+            let wait = {
+                let mut hosts = self.hosts.lock().unwrap();
+                let state = hosts
+                    .entry(host.to_string())
+                    .or_insert_with(|| HostState::new(self.burst));
+                let probe = self.check_circuit_internal(host, state)?;
+                match self.take_token_internal(state) {
+                    Some(delay) => delay,
+                    None => {
+                        let probe = probe.then(|| self.claim_probe_internal(host, state));
+                        return Ok(Permit {
+                            limiter: self,
+                            host: host.to_string(),
+                            probe,
+                        });
+                    }
+                }
+            };
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Reject the request if the circuit is open, or half-open with a probe out.
+    /// Returns whether the request would be the probe of a half-open circuit.
+    fn check_circuit_internal(&self, host: &str, state: &HostState) -> Result<bool, NetworkError> {
+        match state.circuit {
+            CircuitState::Closed => Ok(false),
+            CircuitState::Open => {
+                let opened_at = state.opened_at.unwrap_or_else(Instant::now);
+                if opened_at.elapsed() < self.cooldown {
+                    tracing::debug!(
+                        "Rejecting request to {} after {} consecutive failures",
+                        host,
+                        state.consecutive_failures
+                    );
+                    return Err(NetworkError::CircuitOpen {
+                        host: host.to_string(),
+                    });
+                }
+                Ok(true)
+            }
+            CircuitState::HalfOpen => {
+                if state.probe_in_flight.is_some() {
+                    tracing::debug!("Rejecting request to {} while a probe is in flight", host);
+                    return Err(NetworkError::CircuitOpen {
+                        host: host.to_string(),
+                    });
+                }
+                Ok(true)
+            }
+        }
+    }
+
+    /// Half-open the circuit and make the caller its probe; returns the probe's id.
+    fn claim_probe_internal(&self, host: &str, state: &mut HostState) -> u64 {
+        if state.circuit == CircuitState::Open {
+            tracing::info!("Circuit half-open for host {}, sending probe", host);
+        }
+        let probe = self.next_probe.fetch_add(1, Ordering::Relaxed);
+        state.circuit = CircuitState::HalfOpen;
+        state.probe_in_flight = Some(probe);
+        probe
+    }
+
+    /// Refill the bucket and take a token. Returns how long to wait if none is available.
+    fn take_token_internal(&self, state: &mut HostState) -> Option<Duration> {
+        if self.rate <= 0.0 {
+            return None;
+        }
+
+        let now = Instant::now();
+        let elapsed = now.duration_since(state.last_refill).as_secs_f64();
+        state.tokens = (state.tokens + elapsed * self.rate).min(self.burst);
+        state.last_refill = now;
+
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            None
+        } else {
+            Some(Duration::from_secs_f64((1.0 - state.tokens) / self.rate))
+        }
+    }
+
+    /// Record a successful request, closing the circuit.
+    pub fn record_success(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(state) = hosts.get_mut(host) {
+            if state.circuit != CircuitState::Closed {
+                tracing::info!("Circuit closed for host {}", host);
+            }
+            state.circuit = CircuitState::Closed;
+            state.consecutive_failures = 0;
+            state.opened_at = None;
+            state.probe_in_flight = None;
+        }
+    }
+
+    /// Record a failed request, opening the circuit once the threshold is reached
+    /// or if the half-open probe failed.
+    pub fn record_failure(&self, host: &str) {
+        let mut hosts = self.hosts.lock().unwrap();
+        if let Some(state) = hosts.get_mut(host) {
+            state.consecutive_failures += 1;
+            state.probe_in_flight = None;
+
+            let tripped =
+                self.failure_threshold > 0 && state.consecutive_failures >= self.failure_threshold;
+            if state.circuit == CircuitState::HalfOpen
+                || (state.circuit == CircuitState::Closed && tripped)
+            {
+                tracing::warn!(
+                    "Circuit opened for host {} after {} consecutive failures",
+                    host,
+                    state.consecutive_failures
+                );
+                state.circuit = CircuitState::Open;
+                state.opened_at = Some(Instant::now());
+            }
+        }
+    }
+
+    /// Return a snapshot of every host seen so far, sorted by host.
+    pub fn status(&self) -> Vec<HostStatus> {
+        let hosts = self.hosts.lock().unwrap();
+        let mut status: Vec<_> = hosts
+            .iter()
+            .map(|(host, state)| HostStatus {
+                host: host.clone(),
+                circuit: state.circuit,
+                consecutive_failures: state.consecutive_failures,
+                available_tokens: state.tokens,
+                retry_in_secs: match (state.circuit, state.opened_at) {
+                    (CircuitState::Open, Some(opened_at)) => {
+                        Some(self.cooldown.saturating_sub(opened_at.elapsed()).as_secs())
+                    }
+                    _ => None,
+                },
+            })
+            .collect();
+        status.sort_by(|a, b| a.host.cmp(&b.host));
+        status
+    }
+}
+
+/// The key used to track a URL: its host, plus the port if one is given explicitly.
+/// Returns `None` for URLs without a host.
+pub fn host_key(url: &str) -> Option<String> {
+    let parsed = Url::parse(url).ok()?;
+    let host = parsed.host_str()?;
+    Some(match parsed.port() {
+        Some(port) => format!("{}:{}", host, port),
+        None => host.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::test;
+
+    #[test]
+    async fn test_host_key() {
+        assert_eq!(
+            host_key("https://annas-archive.org/md5/abc").as_deref(),
+            Some("annas-archive.org")
+        );
+        assert_eq!(
+            host_key("http://127.0.0.1:8080/search").as_deref(),
+            Some("127.0.0.1:8080")
+        );
+        assert_eq!(host_key("http://"), None);
+    }
+
+    #[test]
+    async fn test_rate_limit_waits_for_token() {
+        let limiter = HostLimiter::new(20.0, 1, 0, Duration::from_secs(60));
+        let start = Instant::now();
+        limiter.acquire("example.com").await.unwrap();
+        limiter.acquire("example.com").await.unwrap();
+        // The second request has to wait for a refill at 20 tokens per second
+        assert!(start.elapsed() >= Duration::from_millis(40));
+
+        // Other hosts have their own bucket
+        let start = Instant::now();
+        limiter.acquire("example.org").await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(40));
+    }
+
+    #[test]
+    async fn test_circuit_opens_after_threshold() {
+        let limiter = HostLimiter::new(0.0, 1, 2, Duration::from_secs(60));
+        limiter.acquire("example.com").await.unwrap();
+        limiter.record_failure("example.com");
+        limiter.acquire("example.com").await.unwrap();
+        limiter.record_failure("example.com");
+
+        assert!(limiter.acquire("example.com").await.is_err());
+        let status = limiter.status();
+        assert_eq!(status[0].circuit, CircuitState::Open);
+        assert_eq!(status[0].consecutive_failures, 2);
+        assert!(status[0].retry_in_secs.is_some());
+    }
+
+    #[test]
+    async fn test_circuit_half_opens_after_cooldown() {
+        let limiter = HostLimiter::new(0.0, 1, 1, Duration::ZERO);
+        limiter.acquire("example.com").await.unwrap();
+        limiter.record_failure("example.com");
+
+        // The cool-down is over, so one probe is let through, but only one
+        let _probe = limiter.acquire("example.com").await.unwrap();
+        assert_eq!(limiter.status()[0].circuit, CircuitState::HalfOpen);
+        assert!(limiter.acquire("example.com").await.is_err());
+
+        // A successful probe closes the circuit again
+        limiter.record_success("example.com");
+        assert_eq!(limiter.status()[0].circuit, CircuitState::Closed);
+        limiter.acquire("example.com").await.unwrap();
+    }
+
+    #[test]
+    async fn test_failed_probe_reopens_circuit() {
+        let limiter = HostLimiter::new(0.0, 1, 3, Duration::ZERO);
+        for _ in 0..3 {
+            limiter.acquire("example.com").await.unwrap();
+            limiter.record_failure("example.com");
+        }
+        let _probe = limiter.acquire("example.com").await.unwrap();
+        limiter.record_failure("example.com");
+
+        let status = limiter.status();
+        assert_eq!(status[0].circuit, CircuitState::Open);
+        assert_eq!(status[0].consecutive_failures, 4);
+    }
+
+    #[test]
+    async fn test_probe_waiting_for_a_token_is_not_rejected() {
+        // One token per 50ms, so the probe right after the failure has to wait
+        let limiter = HostLimiter::new(20.0, 1, 1, Duration::ZERO);
+        limiter.acquire("example.com").await.unwrap();
+        limiter.record_failure("example.com");
+
+        let probe = limiter.acquire("example.com").await.unwrap();
+        assert_eq!(limiter.status()[0].circuit, CircuitState::HalfOpen);
+        drop(probe);
+
+        // The probe was dropped without an outcome, so another request may probe
+        let _probe = limiter.acquire("example.com").await.unwrap();
+        assert!(limiter.acquire("example.com").await.is_err());
+    }
+
+    #[test]
+    async fn test_dropped_permit_does_not_release_a_later_probe() {
+        let limiter = HostLimiter::new(0.0, 1, 1, Duration::ZERO);
+        limiter.acquire("example.com").await.unwrap();
+        limiter.record_failure("example.com");
+
+        let first = limiter.acquire("example.com").await.unwrap();
+        limiter.record_failure("example.com");
+        let _second = limiter.acquire("example.com").await.unwrap();
+        drop(first);
+        assert!(limiter.acquire("example.com").await.is_err());
+    }
+}
@@ -1,37 +1,321 @@
 mod app;
+mod assets;
 mod book_manager;
+mod cache;
+mod cli;
 mod config;
+mod covers;
 mod handler;
+mod languages;
+mod library;
+mod limiter;
+mod metadata;
+mod mirrors;
 mod models;
 mod network;
+mod opds;
+mod opds_feed;
+mod reload;
+mod search;
+mod server;
+mod sources;
+mod state;
 
-use axum::{routing::get, Router};
-use config::CONFIG;
+use axum::response::Redirect;
+use axum::{extract::Request, routing::get, Router};
+use config::Config;
+use state::AppState;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
 use tower_http::services::{ServeDir, ServeFile};
+use tower_http::trace::TraceLayer;
+use tracing_subscriber::EnvFilter;
 
-#[tokio::main]
-async fn main() {
-    // Access configuration settings using the global CONFIG instance
-    println!("Base Directory: {:?}", CONFIG.base_dir);
+/// Exit status for invalid command line arguments (`EX_USAGE` from sysexits.h).
+const EXIT_USAGE: i32 = 64;
 
-    // Build our application with routes and static files
-    let root_app = Router::new()
+/// Exit status when a required directory cannot be created (`EX_CANTCREAT` from sysexits.h).
+const EXIT_CANTCREAT: i32 = 73;
+
+/// Sequential id attached to every API request span, to correlate its log lines.
+static REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+/// The `api_request` span every incoming request is handled in.
+fn make_request_span(request: &Request) -> tracing::Span {
+    tracing::info_span!(
+        "api_request",
+        request_id = REQUEST_ID.fetch_add(1, Ordering::Relaxed),
+        method = %request.method(),
+        uri = %network::redact_url(&request.uri().to_string()),
+    )
+}
+
+/// Path the Python version served the app under, kept as an alias for old links.
+const LEGACY_URL_BASE: &str = "/request";
+
+/// The JSON API, mounted below `{url_base}/api`.
+fn api_router() -> Router<AppState> {
+    Router::new()
         .route("/info", get(handler::handler_info))
+        .route("/cover", get(handler::handler_cover))
         .route("/search", get(handler::handler_search))
         .route("/download", get(handler::handler_download))
         .route("/status", get(handler::handler_status))
-        .route("/localdownload", get(handler::handler_localdownload));
-    let app = Router::new()
-        // How to make this router to handler mapping better?
-        .route_service("/", ServeFile::new("../static/index.html"))
-        .nest("/api", root_app.clone())
-        .nest("/request/api", root_app)
-        .nest_service("/static", ServeDir::new("../static"));
+        .route("/mirrors", get(handler::handler_mirrors))
+        .route("/admin/hosts", get(handler::handler_admin_hosts))
+        .route("/config", get(handler::handler_config))
+        .route("/languages", get(handler::handler_languages))
+        .route("/localdownload", get(handler::handler_localdownload))
+}
+
+/// The OPDS catalogue, mounted below `{url_base}/opds`.
+fn opds_router() -> Router<AppState> {
+    Router::new()
+        .route("/", get(opds_feed::handler_opds_root))
+        .route("/recent", get(opds_feed::handler_opds_recent))
+        .route("/authors", get(opds_feed::handler_opds_authors))
+        .route("/author", get(opds_feed::handler_opds_author))
+        .route("/languages", get(opds_feed::handler_opds_languages))
+        .route("/language", get(opds_feed::handler_opds_language))
+        .route(
+            "/search.xml",
+            get(opds_feed::handler_opds_search_description),
+        )
+        .route("/search", get(opds_feed::handler_opds_search))
+        .route("/request", get(opds_feed::handler_opds_request))
+        .route("/file", get(opds_feed::handler_opds_file))
+}
+
+/// Add the index page, static files, favicon, API and OPDS catalogue below `url_base`.
+///
+/// The frontend links its assets relative to the page, so `url_base` without the
+/// trailing slash redirects to the page itself.
+fn mount(app: Router<AppState>, url_base: &str, static_dir: Option<&Path>) -> Router<AppState> {
+    let app = app
+        .nest(&format!("{}/api", url_base), api_router())
+        .nest(&format!("{}/opds", url_base), opds_router());
+    let app = if url_base.is_empty() {
+        app
+    } else {
+        let index = format!("{}/", url_base);
+        app.route(
+            url_base,
+            get(move || async move { Redirect::permanent(&index) }),
+        )
+    };
+
+    // Serve the frontend from STATIC_DIR if set, otherwise from the copy embedded in the binary
+    match static_dir {
+        Some(static_dir) => app
+            .route_service(
+                &format!("{}/", url_base),
+                ServeFile::new(static_dir.join("index.html"))
+                    .precompressed_br()
+                    .precompressed_gzip(),
+            )
+            .route_service(
+                &format!("{}/favicon.ico", url_base),
+                ServeFile::new(static_dir.join("media/favicon.ico")),
+            )
+            .nest_service(
+                &format!("{}/static", url_base),
+                ServeDir::new(static_dir)
+                    .precompressed_br()
+                    .precompressed_gzip(),
+            ),
+        None => app
+            .route(&format!("{}/", url_base), get(assets::handler_index))
+            .route(
+                &format!("{}/favicon.ico", url_base),
+                get(assets::handler_favicon),
+            )
+            .route(
+                &format!("{}/static/{{*path}}", url_base),
+                get(assets::handler_static),
+            ),
+    }
+}
+
+/// The whole app under the configured `url_base`, plus the legacy `/request` paths
+/// as an alias.
+fn build_router(state: AppState) -> Router {
+    let config = state.config();
+    let url_base = config.url_base.as_str();
+    let static_dir = config.static_dir.as_deref();
+    let app = mount(Router::new(), url_base, static_dir);
+    let app = if url_base == LEGACY_URL_BASE {
+        app
+    } else {
+        mount(app, LEGACY_URL_BASE, static_dir)
+    };
+    app.with_state(state)
+}
+
+#[tokio::main]
+async fn main() {
+    let cli = cli::parse(std::env::args().skip(1)).unwrap_or_else(|e| {
+        eprintln!("{}\n\n{}", e, cli::USAGE);
+        std::process::exit(EXIT_USAGE);
+    });
+    if cli.command == cli::Command::Help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    // Fail fast with a report of every invalid setting before anything else runs
+    let config = Config::load(&cli.options).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(config::EXIT_CONFIG);
+    });
+    if cli.command == cli::Command::ConfigDump {
+        print!("{}", config.dump());
+        return;
+    }
+
+    // Log through `tracing`; RUST_LOG overrides the default level
+    let default_level = if config.flask_debug { "debug" } else { "info" };
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(default_level)),
+        )
+        .init();
+
+    tracing::info!("Base Directory: {:?}", config.base_dir);
+    config.warn_rejected_languages();
+    if let Err(e) = config.create_dirs() {
+        tracing::error!("{:#}", e);
+        std::process::exit(EXIT_CANTCREAT);
+    }
+    let state = AppState::new(config);
+
+    // Keep track of which Anna's Archive mirrors are reachable
+    mirrors::spawn_health_checker(state.clone());
+    // Pick up changed settings on SIGHUP or when the config file changes
+    reload::spawn_reloader(state.clone(), cli.options);
+
+    // Build our application with routes and static files
+    let app = build_router(state.clone())
+        .layer(TraceLayer::new_for_http().make_span_with(make_request_span));
 
     // run it
-    let listener = tokio::net::TcpListener::bind("127.0.0.1:3000")
-        .await
-        .unwrap();
-    println!("listening on {}", listener.local_addr().unwrap());
-    axum::serve(listener, app).await.unwrap();
+    let config = state.config();
+    if config.static_dir.is_none() {
+        assets::prepare().await;
+    }
+    if let Err(e) = server::serve(app, &config.listen_addresses, config.unix_socket.as_ref()).await
+    {
+        tracing::error!("Server error: {:#}", e);
+        std::process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    use axum::http::{header, StatusCode};
+    use tower::ServiceExt;
+
+    async fn get_status(app: &Router, uri: &str) -> (StatusCode, Option<String>) {
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let location = response
+            .headers()
+            .get(header::LOCATION)
+            .map(|value| value.to_str().unwrap().to_string());
+        (response.status(), location)
+    }
+
+    #[tokio::test]
+    async fn test_app_is_mounted_under_url_base() {
+        let app = build_router(state::test_state(&[("URL_BASE", "/books")]));
+
+        assert_eq!(get_status(&app, "/books/").await.0, StatusCode::OK);
+        assert_eq!(
+            get_status(&app, "/books/static/css/styles.css").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_status(&app, "/books/favicon.ico").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_status(&app, "/books").await,
+            (StatusCode::PERMANENT_REDIRECT, Some("/books/".to_string()))
+        );
+        assert_eq!(get_status(&app, "/").await.0, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_legacy_request_paths_still_work() {
+        for url_base in ["", "/books", "/request"] {
+            let app = build_router(state::test_state(&[("URL_BASE", url_base)]));
+
+            assert_eq!(get_status(&app, "/request/").await.0, StatusCode::OK);
+            assert_eq!(
+                get_status(&app, "/request/static/js/main.js").await.0,
+                StatusCode::OK
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_covers_and_opds_are_mounted_under_url_base() {
+        let state = state::test_state(&[("URL_BASE", "/books")]);
+        state
+            .queue
+            .add("a1", models::BookInfo::new("a1", "No cover"));
+        let app = build_router(state);
+
+        assert_eq!(get_status(&app, "/books/opds").await.0, StatusCode::OK);
+        assert_eq!(
+            get_status(&app, "/books/api/cover?id=a1").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn test_forgotten_opds_books_ask_for_a_new_search() {
+        let app = build_router(state::test_state(&[
+            ("BOOK_SOURCES", "opds"),
+            ("OPDS_URL", "http://127.0.0.1:9/opds"),
+        ]));
+
+        for uri in [
+            "/api/cover?id=opds:urn:uuid:1",
+            "/api/info?id=opds:urn:uuid:1",
+        ] {
+            let response = app
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::NOT_FOUND);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert!(String::from_utf8_lossy(&body).contains("search_again"));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_static_dir_is_served_under_url_base() {
+        let app = build_router(state::test_state(&[
+            ("URL_BASE", "/books"),
+            ("STATIC_DIR", "../static"),
+        ]));
+
+        assert_eq!(get_status(&app, "/books/").await.0, StatusCode::OK);
+        assert_eq!(
+            get_status(&app, "/books/static/js/main.js").await.0,
+            StatusCode::OK
+        );
+        assert_eq!(
+            get_status(&app, "/books/favicon.ico").await.0,
+            StatusCode::OK
+        );
+    }
 }
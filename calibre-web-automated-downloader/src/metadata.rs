@@ -0,0 +1,506 @@
+use serde::Serialize;
+use std::collections::HashMap;
+
+/// A validated ISBN, kept in both forms where both exist.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Isbn {
+    /// The 13 digit form, without separators.
+    pub isbn_13: String,
+    /// The 10 digit form, only for ISBNs with the `978` prefix.
+    pub isbn_10: Option<String>,
+}
+
+impl Isbn {
+    /// Parse an ISBN-10 or ISBN-13, ignoring hyphens and spaces. Returns `None` when
+    /// the length or the check digit is wrong.
+    pub fn parse(value: &str) -> Option<Isbn> {
+        let chars = value
+            .trim()
+            .chars()
+            .filter(|c| !matches!(c, '-' | ' ' | '\u{2010}'..='\u{2015}'))
+            .map(|c| c.to_ascii_uppercase())
+            .collect::<String>();
+        match chars.len() {
+            10 if isbn_10_is_valid(&chars) => Some(Isbn {
+                isbn_13: isbn_10_to_13(&chars),
+                isbn_10: Some(chars),
+            }),
+            13 if isbn_13_is_valid(&chars) => Some(Isbn {
+                isbn_10: isbn_13_to_10(&chars),
+                isbn_13: chars,
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn isbn_10_is_valid(isbn: &str) -> bool {
+    let mut sum = 0;
+    for (i, c) in isbn.chars().enumerate() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'X' if i == 9 => 10,
+            _ => return false,
+        };
+        sum += digit * (10 - i as u32);
+    }
+    sum % 11 == 0
+}
+
+/// The check digit completing the first 12 digits of an ISBN-13.
+fn isbn_13_check_digit(digits: &str) -> u32 {
+    let sum: u32 = digits
+        .chars()
+        .take(12)
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 0 { d } else { d * 3 })
+        .sum();
+    (10 - sum % 10) % 10
+}
+
+fn isbn_13_is_valid(isbn: &str) -> bool {
+    isbn.chars().all(|c| c.is_ascii_digit())
+        && (isbn.starts_with("978") || isbn.starts_with("979"))
+        && isbn[12..].parse::<u32>().ok() == Some(isbn_13_check_digit(isbn))
+}
+
+fn isbn_10_to_13(isbn: &str) -> String {
+    let digits = format!("978{}", &isbn[..9]);
+    let check = isbn_13_check_digit(&digits);
+    format!("{}{}", digits, check)
+}
+
+fn isbn_13_to_10(isbn: &str) -> Option<String> {
+    let digits = isbn.strip_prefix("978")?.get(..9)?;
+    let sum: u32 = digits
+        .chars()
+        .filter_map(|c| c.to_digit(10))
+        .enumerate()
+        .map(|(i, d)| d * (10 - i as u32))
+        .sum();
+    let check = match (11 - sum % 11) % 11 {
+        10 => 'X',
+        d => char::from_digit(d, 10)?,
+    };
+    Some(format!("{}{}", digits, check))
+}
+
+/// A series a book belongs to.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Series {
+    pub name: String,
+    /// The book's position in the series, e.g. `18` or `2.5`.
+    pub index: Option<f32>,
+}
+
+/// Identifiers of a book in other catalogues.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct Identifiers {
+    /// The MD5 of the file, which Anna's Archive and Libgen key files by.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    pub open_library: Vec<String>,
+    pub goodreads: Vec<String>,
+    pub google_books: Vec<String>,
+    /// Libgen file and edition ids.
+    pub libgen: Vec<String>,
+}
+
+impl Identifiers {
+    pub fn is_empty(&self) -> bool {
+        self.md5.is_none()
+            && self.open_library.is_empty()
+            && self.goodreads.is_empty()
+            && self.google_books.is_empty()
+            && self.libgen.is_empty()
+    }
+}
+
+/// Metadata beyond the fields shown in search results.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct BookMetadata {
+    pub isbns: Vec<Isbn>,
+    pub series: Option<Series>,
+    pub edition: Option<String>,
+    pub pages: Option<u32>,
+    pub description: Option<String>,
+    pub subjects: Vec<String>,
+    #[serde(skip_serializing_if = "Identifiers::is_empty")]
+    pub identifiers: Identifiers,
+}
+
+/// The fields of a detail page, split into the ones with a typed home and the rest.
+#[derive(Debug, Default)]
+pub struct ParsedFields {
+    pub metadata: BookMetadata,
+    pub language: Option<String>,
+    pub year: Option<String>,
+    /// Fields without a typed home, and values that failed validation.
+    pub raw: HashMap<String, Vec<String>>,
+}
+
+fn push_unique(values: &mut Vec<String>, value: &str) {
+    if !values.iter().any(|v| v == value) {
+        values.push(value.to_string());
+    }
+}
+
+/// Split `Name #3`, `Name, Vol. 3` or `Name (3)` into the series name and index.
+fn parse_series(value: &str) -> Series {
+    let value = value.trim();
+    let split = value.rfind(['#', ',', '(']).map(|i| {
+        (
+            value[..i].trim(),
+            value[i + 1..].trim_end_matches(')').trim(),
+        )
+    });
+    if let Some((name, index)) = split {
+        let index = index
+            .trim_start_matches(|c: char| c.is_alphabetic() || c == '.' || c == ' ')
+            .parse::<f32>();
+        if let (false, Ok(index)) = (name.is_empty(), index) {
+            return Series {
+                name: name.to_string(),
+                index: Some(index),
+            };
+        }
+    }
+    Series {
+        name: value.to_string(),
+        index: None,
+    }
+}
+
+/// Split an author line such as `Fujino Omori and Suzuhito Yasuda` into its authors.
+///
+/// Commas only separate authors when every part is a full name, so that
+/// `Tolkien, J.R.R.` stays one author.
+pub fn split_authors(value: &str) -> Vec<String> {
+    let mut authors = Vec::new();
+    for part in value.split([';', '&']).flat_map(|part| part.split(" and ")) {
+        let names = part.split(',').map(str::trim).collect::<Vec<_>>();
+        if names.len() > 1 && names.iter().all(|name| name.contains(' ')) {
+            authors.extend(names.iter().map(|name| name.to_string()));
+        } else {
+            authors.push(part.trim().to_string());
+        }
+    }
+    authors.retain(|author| !author.is_empty());
+    authors
+}
+
+/// The parts of Anna's Archive's combined publisher line,
+/// e.g. `Yen On, Is It Wrong to Try to Pick Up Girls in a Dungeon?, 18, 2023`.
+#[derive(Debug, Default, PartialEq)]
+pub struct PublisherLine {
+    pub publisher: Option<String>,
+    pub series: Option<Series>,
+    /// What follows the publisher when it is not a numbered series, e.g. `2nd edition`.
+    pub edition: Option<String>,
+    pub year: Option<String>,
+}
+
+impl PublisherLine {
+    /// Split the line at its commas: the first part is the publisher, a trailing
+    /// four digit number the year, and a number before it the series index of the
+    /// parts in between.
+    pub fn parse(value: &str) -> PublisherLine {
+        let mut parts = value
+            .split(", ")
+            .map(str::trim)
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+        let mut line = PublisherLine::default();
+
+        let is_year = |part: &str| part.len() == 4 && part.chars().all(|c| c.is_ascii_digit());
+        if parts.len() > 1 && parts.last().is_some_and(|part| is_year(part)) {
+            line.year = parts.pop().map(str::to_string);
+        }
+        let index = match parts.len() > 2 {
+            true => parts.last().and_then(|part| part.parse::<f32>().ok()),
+            false => None,
+        };
+        if index.is_some() {
+            parts.pop();
+        }
+
+        if parts.is_empty() {
+            return line;
+        }
+        line.publisher = Some(parts.remove(0).to_string());
+        let rest = parts.join(", ");
+        if !rest.is_empty() {
+            match index {
+                Some(index) => {
+                    line.series = Some(Series {
+                        name: rest,
+                        index: Some(index),
+                    })
+                }
+                None => line.edition = Some(rest),
+            }
+        }
+        line
+    }
+}
+
+impl ParsedFields {
+    /// Sort `(label, value)` pairs from a detail page into typed fields. Labels are
+    /// matched case-insensitively, with any trailing `:` ignored.
+    pub fn from_pairs<'a>(pairs: impl IntoIterator<Item = (&'a str, &'a str)>) -> Self {
+        let mut fields = ParsedFields::default();
+        for (label, value) in pairs {
+            let label = label.trim().trim_end_matches(':').trim();
+            let value = value.trim();
+            if label.is_empty() || value.is_empty() {
+                continue;
+            }
+            if !fields.apply(label, value) {
+                let values = fields.raw.entry(label.to_string()).or_default();
+                push_unique(values, value);
+            }
+        }
+        fields
+    }
+
+    /// Store a field in its typed home; `false` when there is none or the value is invalid.
+    fn apply(&mut self, label: &str, value: &str) -> bool {
+        let metadata = &mut self.metadata;
+        match label.to_lowercase().as_str() {
+            "isbn" | "isbn-10" | "isbn10" | "isbn-13" | "isbn13" => {
+                let Some(isbn) = Isbn::parse(value) else {
+                    return false;
+                };
+                if !metadata.isbns.contains(&isbn) {
+                    metadata.isbns.push(isbn);
+                }
+            }
+            "series" => metadata.series = Some(parse_series(value)),
+            "series index" | "volume" => {
+                let Ok(index) = value.parse::<f32>() else {
+                    return false;
+                };
+                match &mut metadata.series {
+                    Some(series) => series.index = Some(index),
+                    None => return false,
+                }
+            }
+            "edition" => metadata.edition = Some(value.to_string()),
+            "pages" | "page count" | "number of pages" => {
+                let digits = value
+                    .chars()
+                    .take_while(char::is_ascii_digit)
+                    .collect::<String>();
+                match digits.parse() {
+                    Ok(pages) => metadata.pages = Some(pages),
+                    Err(_) => return false,
+                }
+            }
+            "description" => metadata.description = Some(value.to_string()),
+            "subject" | "subjects" | "topic" | "topics" | "tags" => {
+                for subject in value.split([';', ',']).map(str::trim) {
+                    if !subject.is_empty() {
+                        push_unique(&mut metadata.subjects, subject);
+                    }
+                }
+            }
+            "open library" | "openlibrary" | "ol" => {
+                push_unique(&mut metadata.identifiers.open_library, value)
+            }
+            "goodreads" => push_unique(&mut metadata.identifiers.goodreads, value),
+            "google books" | "googlebooks" => {
+                push_unique(&mut metadata.identifiers.google_books, value)
+            }
+            label if label.starts_with("libgen") && value.chars().all(|c| c.is_ascii_digit()) => {
+                push_unique(&mut metadata.identifiers.libgen, value)
+            }
+            "language" => {
+                self.language.get_or_insert_with(|| value.to_string());
+            }
+            "year" => {
+                self.year.get_or_insert_with(|| value.to_string());
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_isbn_normalisation() {
+        let isbn = Isbn::parse("0-261-10320-2").unwrap();
+        assert_eq!(isbn.isbn_13, "9780261103207");
+        assert_eq!(isbn.isbn_10.as_deref(), Some("0261103202"));
+        assert_eq!(Isbn::parse("978-0-261-10320-7"), Some(isbn));
+
+        let isbn = Isbn::parse("080442957x").unwrap();
+        assert_eq!(isbn.isbn_10.as_deref(), Some("080442957X"));
+        assert_eq!(isbn.isbn_13, "9780804429573");
+        assert_eq!(
+            Isbn::parse("9780804429573").unwrap().isbn_10.as_deref(),
+            Some("080442957X")
+        );
+
+        // 979 ISBNs have no 10 digit form
+        let isbn = Isbn::parse("979-10-90636-07-1").unwrap();
+        assert_eq!(isbn.isbn_10, None);
+    }
+
+    #[test]
+    fn test_isbn_checksum() {
+        assert_eq!(Isbn::parse("0-261-10320-3"), None);
+        assert_eq!(Isbn::parse("9780261103208"), None);
+        assert_eq!(Isbn::parse("1234567890123"), None);
+        assert_eq!(Isbn::parse("02611X3202"), None);
+        assert_eq!(Isbn::parse("not an isbn"), None);
+    }
+
+    #[test]
+    fn test_parse_series() {
+        assert_eq!(
+            parse_series("The Lord of the Rings #2"),
+            Series {
+                name: "The Lord of the Rings".to_string(),
+                index: Some(2.0)
+            }
+        );
+        assert_eq!(parse_series("Dungeon, Vol. 18").index, Some(18.0));
+        assert_eq!(parse_series("Discworld (2.5)").index, Some(2.5));
+        assert_eq!(
+            parse_series("Standalone"),
+            Series {
+                name: "Standalone".to_string(),
+                index: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_split_authors() {
+        assert_eq!(
+            split_authors("Fujino Omori and Suzuhito Yasuda"),
+            vec!["Fujino Omori", "Suzuhito Yasuda"]
+        );
+        assert_eq!(split_authors("J. R. R. Tolkien"), vec!["J. R. R. Tolkien"]);
+        assert_eq!(split_authors("TOLKIEN, J.R.R."), vec!["TOLKIEN, J.R.R."]);
+        assert_eq!(
+            split_authors("Terry Pratchett, Neil Gaiman"),
+            vec!["Terry Pratchett", "Neil Gaiman"]
+        );
+        assert_eq!(
+            split_authors("Omori, Fujino; Yasuda, Suzuhito & Kiyotaka Haimura"),
+            vec!["Omori, Fujino", "Yasuda, Suzuhito", "Kiyotaka Haimura"]
+        );
+        assert!(split_authors(" ").is_empty());
+    }
+
+    #[test]
+    fn test_publisher_line() {
+        assert_eq!(
+            PublisherLine::parse(
+                "Yen On, Is It Wrong to Try to Pick Up Girls in a Dungeon?, 18, 2023"
+            ),
+            PublisherLine {
+                publisher: Some("Yen On".to_string()),
+                series: Some(Series {
+                    name: "Is It Wrong to Try to Pick Up Girls in a Dungeon?".to_string(),
+                    index: Some(18.0)
+                }),
+                edition: None,
+                year: Some("2023".to_string()),
+            }
+        );
+        assert_eq!(
+            PublisherLine::parse("Penguin, 2nd edition, 2005"),
+            PublisherLine {
+                publisher: Some("Penguin".to_string()),
+                edition: Some("2nd edition".to_string()),
+                year: Some("2005".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            PublisherLine::parse("cj5_7301"),
+            PublisherLine {
+                publisher: Some("cj5_7301".to_string()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(PublisherLine::parse("2005").year, None);
+        assert_eq!(PublisherLine::parse(""), PublisherLine::default());
+    }
+
+    #[test]
+    fn test_fields_from_pairs() {
+        let fields = ParsedFields::from_pairs([
+            ("ISBN-13", "978-0-261-10320-7"),
+            ("ISBN-10", "0261103202"),
+            ("ISBN-13", "9780000000000"),
+            ("Series", "The Lord of the Rings"),
+            ("Series index", "1"),
+            ("Edition", "2nd edition"),
+            ("Pages:", "1216 pages"),
+            ("Subjects", "Fantasy; Fiction, Fantasy"),
+            ("Open Library", "OL27479W"),
+            ("Goodreads", "33"),
+            ("Libgen.li File", "101864185"),
+            ("Libgen.li Source Date", "2023-07-13"),
+            ("Language", "en"),
+            ("Alternative author", "TOLKIEN, J.R.R."),
+            ("Empty", ""),
+        ]);
+
+        let metadata = &fields.metadata;
+        assert_eq!(metadata.isbns.len(), 1);
+        assert_eq!(metadata.isbns[0].isbn_13, "9780261103207");
+        assert_eq!(
+            metadata.series,
+            Some(Series {
+                name: "The Lord of the Rings".to_string(),
+                index: Some(1.0)
+            })
+        );
+        assert_eq!(metadata.edition.as_deref(), Some("2nd edition"));
+        assert_eq!(metadata.pages, Some(1216));
+        assert_eq!(metadata.subjects, vec!["Fantasy", "Fiction"]);
+        assert_eq!(metadata.identifiers.open_library, vec!["OL27479W"]);
+        assert_eq!(metadata.identifiers.goodreads, vec!["33"]);
+        assert_eq!(metadata.identifiers.libgen, vec!["101864185"]);
+        assert_eq!(fields.language.as_deref(), Some("en"));
+
+        // Unknown fields and invalid values are kept as they are
+        assert_eq!(fields.raw["Alternative author"], vec!["TOLKIEN, J.R.R."]);
+        assert_eq!(fields.raw["ISBN-13"], vec!["9780000000000"]);
+        assert_eq!(fields.raw["Libgen.li Source Date"], vec!["2023-07-13"]);
+        assert!(!fields.raw.contains_key("Empty"));
+    }
+
+    proptest! {
+        #[test]
+        fn test_parsers_never_panic(value in ".*") {
+            let _ = Isbn::parse(&value);
+            let _ = parse_series(&value);
+            let _ = PublisherLine::parse(&value);
+            prop_assert!(split_authors(&value).iter().all(|author| !author.is_empty()));
+        }
+
+        #[test]
+        fn test_isbn_like_values(value in "[0-9Xx -]{8,17}") {
+            if let Some(isbn) = Isbn::parse(&value) {
+                prop_assert_eq!(isbn.isbn_13.len(), 13);
+                prop_assert_eq!(Isbn::parse(&isbn.isbn_13), Some(isbn.clone()));
+            }
+        }
+
+        #[test]
+        fn test_labelled_fields_never_panic(label in ".*", value in ".*") {
+            let _ = ParsedFields::from_pairs([(label.as_str(), value.as_str())]);
+        }
+    }
+}
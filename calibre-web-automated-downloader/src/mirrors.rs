@@ -0,0 +1,364 @@
+use crate::network;
+use crate::state::AppState;
+use anyhow::{anyhow, Result};
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// How long a single health probe may take before the mirror is considered down.
+const HEALTH_CHECK_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Health information tracked for a single Anna's Archive mirror.
+#[derive(Debug)]
+struct MirrorState {
+    base_url: String,
+    healthy: bool,
+    consecutive_failures: u64,
+    last_error: Option<String>,
+    last_checked: Option<Instant>,
+}
+
+/// The **data** behind the mirror pool, guarded by the `Mutex` in `MirrorPool`.
+#[derive(Debug)]
+struct MirrorPoolData {
+    mirrors: Vec<MirrorState>,
+    active: usize,
+}
+
+/// Snapshot of a single mirror, as reported by the status endpoint.
+#[derive(Clone, Debug, Serialize)]
+pub struct MirrorStatus {
+    pub base_url: String,
+    pub healthy: bool,
+    pub consecutive_failures: u64,
+    pub last_error: Option<String>,
+    /// Seconds since the mirror was last checked or used, if ever.
+    pub last_checked_secs: Option<u64>,
+}
+
+/// Snapshot of the whole pool, as reported by the status endpoint.
+#[derive(Clone, Debug, Serialize)]
+pub struct MirrorPoolStatus {
+    pub active: String,
+    pub mirrors: Vec<MirrorStatus>,
+}
+
+/// Thread-safe pool of Anna's Archive base URLs with health tracking.
+///
+/// Mirrors are kept in configuration order; the first one is the preferred mirror.
+/// Requests go to the active mirror first and fail over to the remaining healthy
+/// ones, while unhealthy mirrors are only tried as a last resort.
+#[derive(Debug)]
+pub struct MirrorPool {
+    data: Mutex<MirrorPoolData>,
+}
+
+impl MirrorPool {
+    /// Create a pool from a list of base URLs. All mirrors start out healthy.
+    pub fn new(base_urls: Vec<String>) -> Self {
+        let mirrors = base_urls
+            .into_iter()
+            .map(|base_url| MirrorState {
+                base_url,
+                healthy: true,
+                consecutive_failures: 0,
+                last_error: None,
+                last_checked: None,
+            })
+            .collect();
+        MirrorPool {
+            data: Mutex::new(MirrorPoolData { mirrors, active: 0 }),
+        }
+    }
+
+    /// Base URLs in the order they should be tried: the active mirror, the other
+    /// healthy mirrors, and finally the unhealthy ones.
+    pub fn candidates(&self) -> Vec<String> {
+        let data = self.data.lock().unwrap();
+        let count = data.mirrors.len();
+        let ordered = (0..count).map(|i| &data.mirrors[(data.active + i) % count]);
+
+        let (healthy, unhealthy): (Vec<_>, Vec<_>) = ordered.partition(|m| m.healthy);
+        healthy
+            .into_iter()
+            .chain(unhealthy)
+            .map(|m| m.base_url.clone())
+            .collect()
+    }
+
+    /// Record a successful request; the mirror becomes the active one.
+    pub fn mark_success(&self, base_url: &str) {
+        let mut data = self.data.lock().unwrap();
+        if let Some(index) = data.mirrors.iter().position(|m| m.base_url == base_url) {
+            let mirror = &mut data.mirrors[index];
+            mirror.healthy = true;
+            mirror.consecutive_failures = 0;
+            mirror.last_error = None;
+            mirror.last_checked = Some(Instant::now());
+            data.active = index;
+        }
+    }
+
+    /// Record a failed request; if it was the active mirror, move on to the next healthy one.
+    pub fn mark_failure(&self, base_url: &str, error: &str) {
+        let mut data = self.data.lock().unwrap();
+        if let Some(index) = data.mirrors.iter().position(|m| m.base_url == base_url) {
+            let mirror = &mut data.mirrors[index];
+            mirror.healthy = false;
+            mirror.consecutive_failures += 1;
+            mirror.last_error = Some(error.to_string());
+            mirror.last_checked = Some(Instant::now());
+            if data.active == index {
+                Self::select_active_internal(&mut data, index);
+            }
+        }
+    }
+
+    /// Point `active` at the first healthy mirror, searching from `start`.
+    /// Leaves it unchanged when no mirror is healthy.
+    fn select_active_internal(data: &mut MirrorPoolData, start: usize) {
+        let count = data.mirrors.len();
+        if let Some(index) = (0..count)
+            .map(|i| (start + i) % count)
+            .find(|&i| data.mirrors[i].healthy)
+        {
+            data.active = index;
+        }
+    }
+
+    /// Probe every mirror once and update its health.
+    ///
+    /// Afterwards the first healthy mirror in configuration order becomes active,
+    /// so the preferred mirror is picked up again as soon as it recovers.
+    pub async fn check_health(&self) {
+        for base_url in self.base_urls() {
+            match network::probe_url(&base_url, HEALTH_CHECK_TIMEOUT).await {
+                Ok(()) => {
+                    tracing::debug!("Mirror {} is healthy", base_url);
+                    self.mark_success(&base_url);
+                }
+                Err(e) => {
+                    tracing::warn!("Mirror {} failed health check: {}", base_url, e);
+                    self.mark_failure(&base_url, &e.to_string());
+                }
+            }
+        }
+
+        let mut data = self.data.lock().unwrap();
+        Self::select_active_internal(&mut data, 0);
+    }
+
+    /// Fetch `path` (including any query string) from the first mirror that answers,
+    /// failing over through `candidates()`.
+    ///
+    /// Only failures of the mirror itself cause a failover; an answer such as a 404 is
+    /// returned as is. If every mirror fails, the last mirror's `NetworkError` is kept
+    /// as the error's cause.
+    pub async fn html_get_page(&self, state: &AppState, path: &str) -> Result<String> {
+        let mut last_error = None;
+        for base_url in self.candidates() {
+            match network::html_get_page(state, format!("{}{}", base_url, path)).await {
+                Ok(html) => {
+                    self.mark_success(&base_url);
+                    return Ok(html);
+                }
+                Err(e) if !e.is_host_failure() => {
+                    self.mark_success(&base_url);
+                    return Err(e.into());
+                }
+                Err(e) => {
+                    tracing::warn!("Mirror {} failed, trying next mirror: {}", base_url, e);
+                    self.mark_failure(&base_url, &e.to_string());
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        match last_error {
+            Some(e) => Err(anyhow::Error::new(e).context("All mirrors failed")),
+            None => Err(anyhow!("No mirrors configured")),
+        }
+    }
+
+    /// Return a serializable snapshot of the pool.
+    pub fn status(&self) -> MirrorPoolStatus {
+        let data = self.data.lock().unwrap();
+        let now = Instant::now();
+        MirrorPoolStatus {
+            active: data
+                .mirrors
+                .get(data.active)
+                .map(|m| m.base_url.clone())
+                .unwrap_or_default(),
+            mirrors: data
+                .mirrors
+                .iter()
+                .map(|m| MirrorStatus {
+                    base_url: m.base_url.clone(),
+                    healthy: m.healthy,
+                    consecutive_failures: m.consecutive_failures,
+                    last_error: m.last_error.clone(),
+                    last_checked_secs: m.last_checked.map(|ts| now.duration_since(ts).as_secs()),
+                })
+                .collect(),
+        }
+    }
+
+    fn base_urls(&self) -> Vec<String> {
+        let data = self.data.lock().unwrap();
+        data.mirrors.iter().map(|m| m.base_url.clone()).collect()
+    }
+}
+
+/// Spawn a background task that health-checks the state's mirror pool every
+/// `mirror_health_check_interval` seconds. An interval of 0 disables it.
+pub fn spawn_health_checker(state: AppState) {
+    if state.config().mirror_health_check_interval == 0 {
+        return;
+    }
+    let interval = Duration::from_secs(state.config().mirror_health_check_interval);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            state.mirrors.check_health().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_state;
+    use tokio::test;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    fn pool(urls: &[&str]) -> MirrorPool {
+        MirrorPool::new(urls.iter().map(|u| u.to_string()).collect())
+    }
+
+    #[test]
+    async fn test_candidates_start_with_active() {
+        let pool = pool(&["https://a", "https://b", "https://c"]);
+        assert_eq!(pool.status().active, "https://a");
+        assert_eq!(
+            pool.candidates(),
+            vec!["https://a", "https://b", "https://c"]
+        );
+
+        pool.mark_success("https://b");
+        assert_eq!(pool.status().active, "https://b");
+        assert_eq!(
+            pool.candidates(),
+            vec!["https://b", "https://c", "https://a"]
+        );
+    }
+
+    #[test]
+    async fn test_failure_moves_active_and_demotes_mirror() {
+        let pool = pool(&["https://a", "https://b", "https://c"]);
+        pool.mark_failure("https://a", "timeout");
+
+        assert_eq!(pool.status().active, "https://b");
+        assert_eq!(
+            pool.candidates(),
+            vec!["https://b", "https://c", "https://a"]
+        );
+
+        let status = pool.status();
+        assert_eq!(status.active, "https://b");
+        assert!(!status.mirrors[0].healthy);
+        assert_eq!(status.mirrors[0].consecutive_failures, 1);
+        assert_eq!(status.mirrors[0].last_error.as_deref(), Some("timeout"));
+    }
+
+    #[test]
+    async fn test_all_unhealthy_keeps_active() {
+        let pool = pool(&["https://a", "https://b"]);
+        pool.mark_failure("https://a", "down");
+        pool.mark_failure("https://b", "down");
+
+        assert_eq!(pool.status().active, "https://b");
+        assert_eq!(pool.candidates().len(), 2);
+    }
+
+    #[test]
+    async fn test_health_check_and_failover() {
+        let broken = MockServer::start().await;
+        let working = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&broken)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&working)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/search"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("results"))
+            .mount(&working)
+            .await;
+
+        let pool = MirrorPool::new(vec![broken.uri(), working.uri()]);
+        pool.check_health().await;
+        assert_eq!(pool.status().active, working.uri());
+
+        let html = pool
+            .html_get_page(&test_state(&[]), "/search?q=test")
+            .await
+            .unwrap();
+        assert_eq!(html, "results");
+        assert_eq!(pool.status().active, working.uri());
+    }
+
+    #[test]
+    async fn test_not_found_does_not_fail_over() {
+        let first = MockServer::start().await;
+        let second = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(404))
+            .expect(1)
+            .mount(&first)
+            .await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&second)
+            .await;
+
+        let pool = MirrorPool::new(vec![first.uri(), second.uri()]);
+        let error = pool
+            .html_get_page(&test_state(&[]), "/md5/missing")
+            .await
+            .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<network::NetworkError>(),
+            Some(network::NetworkError::Status { code: 404, .. })
+        ));
+        assert_eq!(pool.status().active, first.uri());
+    }
+
+    #[test]
+    async fn test_health_check_restores_preferred_mirror() {
+        let preferred = MockServer::start().await;
+        let fallback = MockServer::start().await;
+        for server in [&preferred, &fallback] {
+            Mock::given(method("GET"))
+                .respond_with(ResponseTemplate::new(200))
+                .mount(server)
+                .await;
+        }
+
+        let pool = MirrorPool::new(vec![preferred.uri(), fallback.uri()]);
+        pool.mark_failure(&preferred.uri(), "down");
+        assert_eq!(pool.status().active, fallback.uri());
+
+        pool.check_health().await;
+        assert_eq!(pool.status().active, preferred.uri());
+    }
+}
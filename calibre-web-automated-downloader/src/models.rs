@@ -1,16 +1,21 @@
-use once_cell::sync::Lazy;
 use proptest_derive::Arbitrary;
+use serde::Serialize;
 use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::path::PathBuf;
 use std::sync::Mutex;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
 // Bring the macros and other important things into scope.
 use proptest::prelude::*;
 
-use crate::config::CONFIG;
+use crate::config::Config;
+use crate::library::Duplicate;
+use crate::metadata::BookMetadata;
 
 /// An enum for possible book queue statuses.
-#[derive(Clone, Debug, PartialEq, Eq, Hash, Arbitrary)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Arbitrary, Serialize)]
+#[serde(rename_all = "lowercase")]
 pub enum QueueStatus {
     Queued,
     Downloading,
@@ -19,57 +24,83 @@ pub enum QueueStatus {
     Done,
 }
 
-impl ToString for QueueStatus {
-    fn to_string(&self) -> String {
-        match self {
+impl fmt::Display for QueueStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
             QueueStatus::Queued => "queued",
             QueueStatus::Downloading => "downloading",
             QueueStatus::Available => "available",
             QueueStatus::Error => "error",
             QueueStatus::Done => "done",
-        }
-        .to_string()
+        })
     }
 }
 
 /// Data structure representing book information.
-#[derive(Clone, Debug, Default)]
+#[derive(Clone, Debug, Default, Serialize)]
 pub struct BookInfo {
+    /// Anna's Archive's MD5 id, or `<source>:<id>` for books from other sources.
     pub id: String,
+    /// The name of the [`crate::sources::BookSource`] the book was found in.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub source: String,
     pub title: String,
     pub preview: Option<String>,
+    /// The author line as shown by the source.
     pub author: Option<String>,
+    /// The authors in `author`, one per entry.
+    pub authors: Vec<String>,
     pub publisher: Option<String>,
     pub year: Option<String>,
     pub language: Option<String>,
     pub format: Option<String>,
     pub size: Option<String>,
 
-    /// e.g. info: { "isbn": ["1234", "9876"], "tags": ["something"] }
+    /// ISBNs, series, identifiers and other details from the book's page.
+    pub metadata: BookMetadata,
+
+    /// Details without a field in `metadata`, as found on the page,
+    /// e.g. info: { "Alternative author": ["TOLKIEN, J.R.R."] }
     pub info: Option<HashMap<String, Vec<String>>>,
 
     /// e.g. a list of direct download URLs
     pub download_urls: Vec<String>,
+
+    /// A copy already in the library, when duplicate checks are on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate: Option<Duplicate>,
 }
 
 impl BookInfo {
     pub fn new(id: &str, title: &str) -> Self {
         Self {
             id: id.to_owned(),
+            source: String::new(),
             title: title.to_owned(),
             preview: None,
             author: None,
+            authors: vec![],
             publisher: None,
             year: None,
             language: None,
             format: None,
             size: None,
+            metadata: BookMetadata::default(),
             info: None,
             download_urls: vec![],
+            duplicate: None,
         }
     }
+
+    /// The format the book is downloaded in, EPUB if its source did not say.
+    pub fn file_format(&self) -> &str {
+        self.format.as_deref().unwrap_or(DEFAULT_FORMAT)
+    }
 }
 
+/// The format assumed for books whose format is unknown.
+pub const DEFAULT_FORMAT: &str = "epub";
+
 /// The **data** holding all the shared state of the queue.
 /// We do not expose this directly because callers
 /// should only interact with the public methods on `BookQueue`.
@@ -83,29 +114,56 @@ struct BookQueueData {
     status_timeout: Duration,
 }
 
+/// The file name, without extension, a book is downloaded and ingested under. Ids
+/// from sources other than Anna's Archive may hold `:` or `/`, which are replaced.
+pub fn file_stem(book_id: &str) -> String {
+    book_id.replace(|c: char| !(c.is_ascii_alphanumeric() || c == '-'), "_")
+}
+
+/// A book in the queue, with its status and when that status was set.
+#[derive(Clone, Debug)]
+pub struct QueueEntry {
+    pub id: String,
+    pub status: QueueStatus,
+    pub book: BookInfo,
+    /// The format the book is downloaded and ingested in.
+    pub format: String,
+    pub updated: SystemTime,
+}
+
 /// Thread-safe book queue manager.
 /// All concurrency is handled by the internal `Mutex`.
 #[derive(Debug)]
 pub struct BookQueue {
     data: Mutex<BookQueueData>,
+    /// Where finished books end up; an available book whose file is gone is done.
+    ingest_dir: PathBuf,
 }
 
 impl BookQueue {
-    /// Create a new empty queue with a default timeout (from CONFIG).
-    pub fn new() -> Self {
-        let timeout_secs = CONFIG.status_timeout;
+    /// Create a new empty queue.
+    pub fn new(status_timeout: Duration, ingest_dir: PathBuf) -> Self {
         let data = BookQueueData {
             queue: HashSet::new(),
             status: HashMap::new(),
             book_data: HashMap::new(),
             status_timestamps: HashMap::new(),
-            status_timeout: Duration::from_secs(timeout_secs),
+            status_timeout,
         };
         BookQueue {
             data: Mutex::new(data),
+            ingest_dir,
         }
     }
 
+    /// Create a new empty queue with the status timeout and ingest directory of `config`.
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(
+            Duration::from_secs(config.status_timeout),
+            config.ingest_dir.clone(),
+        )
+    }
+
     /// Internal helper to update the status + timestamp for a book ID.
     fn update_status_internal(data: &mut BookQueueData, book_id: &str, status: QueueStatus) {
         data.status.insert(book_id.to_string(), status);
@@ -122,6 +180,7 @@ impl BookQueue {
     }
 
     /// Get the next book in the queue (if any).
+    #[allow(dead_code)]
     pub fn get_next(&self) -> Option<String> {
         let mut data = self.data.lock().unwrap();
         if let Some(book_id) = data.queue.iter().next().cloned() {
@@ -132,12 +191,19 @@ impl BookQueue {
         }
     }
 
+    /// Get the stored information of a queued book.
+    pub fn get_book(&self, book_id: &str) -> Option<BookInfo> {
+        let data = self.data.lock().unwrap();
+        data.book_data.get(book_id).cloned()
+    }
+
     /// Update the status of an existing book.
+    #[allow(dead_code)]
     pub fn update_status(&self, book_id: &str, status: QueueStatus) {
         let mut data = self.data.lock().unwrap();
-        log::debug!("Checking status of {} to {:?}", book_id, status);
+        tracing::debug!("Checking status of {} to {:?}", book_id, status);
         if data.status.contains_key(book_id) {
-            log::debug!("Updating status of {} to {:?}", book_id, status);
+            tracing::debug!("Updating status of {} to {:?}", book_id, status);
             Self::update_status_internal(&mut data, book_id, status);
         }
     }
@@ -147,13 +213,11 @@ impl BookQueue {
         let mut data = self.data.lock().unwrap();
 
         // First refresh to remove stale/done items
-        Self::refresh_internal(&mut data);
+        self.refresh_internal(&mut data);
 
-        // Build a HashMap<QueueStatus, HashMap<String, BookInfo>>
-        let mut result = HashMap::<QueueStatus, HashMap<String, BookInfo>>::new();
-
-        // Pre-populate each status variant with an empty map
-        result = HashMap::from([
+        // Build a HashMap<QueueStatus, HashMap<String, BookInfo>>,
+        // pre-populated with an empty map for each status variant
+        let mut result = HashMap::from([
             (QueueStatus::Queued, HashMap::new()),
             (QueueStatus::Downloading, HashMap::new()),
             (QueueStatus::Available, HashMap::new()),
@@ -173,37 +237,82 @@ impl BookQueue {
         result
     }
 
+    /// Every book in the queue, most recently updated first.
+    pub fn history(&self) -> Vec<QueueEntry> {
+        let mut data = self.data.lock().unwrap();
+        self.refresh_internal(&mut data);
+        let mut entries = data
+            .status
+            .iter()
+            .filter_map(|(book_id, status)| {
+                Some(QueueEntry {
+                    id: book_id.clone(),
+                    status: status.clone(),
+                    book: data.book_data.get(book_id)?.clone(),
+                    format: Self::format_internal(&data, book_id).to_string(),
+                    updated: SystemTime::now() - data.status_timestamps.get(book_id)?.elapsed(),
+                })
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|entry| std::cmp::Reverse(entry.updated));
+        entries
+    }
+
+    /// The format a book is downloaded and ingested in.
+    pub fn format(&self, book_id: &str) -> String {
+        let data = self.data.lock().unwrap();
+        Self::format_internal(&data, book_id).to_string()
+    }
+
+    /// Where a finished book is found in the ingest directory.
+    pub fn ingest_path(&self, book_id: &str) -> PathBuf {
+        let data = self.data.lock().unwrap();
+        self.ingest_path_internal(&data, book_id)
+    }
+
+    /// Books no longer in the queue are looked for as EPUB.
+    fn format_internal<'a>(data: &'a BookQueueData, book_id: &str) -> &'a str {
+        data.book_data
+            .get(book_id)
+            .map_or(DEFAULT_FORMAT, BookInfo::file_format)
+    }
+
+    fn ingest_path_internal(&self, data: &BookQueueData, book_id: &str) -> PathBuf {
+        let format = Self::format_internal(data, book_id);
+        self.ingest_dir
+            .join(format!("{}.{}", file_stem(book_id), format))
+    }
+
     /// Refresh the queue by:
-    /// - Checking if "AVAILABLE" books have an .epub file; if not, mark them DONE.
+    /// - Checking if "AVAILABLE" books have their file; if not, mark them DONE.
     /// - Removing stale entries that have exceeded the status_timeout (but only if they are DONE).
-    fn refresh_internal(data: &mut BookQueueData) {
+    fn refresh_internal(&self, data: &mut BookQueueData) {
         let now = Instant::now();
         let mut to_update = Vec::new();
         let mut to_remove = Vec::new();
 
-        log::debug!("Refreshing queue...");
+        tracing::debug!("Refreshing queue...");
 
         // First pass: record changes
         for (book_id, status) in &data.status {
-            log::debug!("Checking status of {}: {:?}", book_id, status);
-            if *status == QueueStatus::Available {
-                let path = CONFIG.ingest_dir.join(format!("{}.epub", book_id));
-                if !path.exists() {
-                    to_update.push(book_id.clone());
-                }
+            tracing::debug!("Checking status of {}: {:?}", book_id, status);
+            if *status == QueueStatus::Available
+                && !self.ingest_path_internal(data, book_id).exists()
+            {
+                to_update.push(book_id.clone());
             }
 
             if let Some(ts) = data.status_timestamps.get(book_id) {
-                log::debug!(
+                tracing::debug!(
                     "Checking timestamp of {}: {:?}, {:?}",
                     book_id,
                     now.duration_since(*ts),
                     data.status_timeout
                 );
                 if now.duration_since(*ts) > data.status_timeout {
-                    log::debug!("Stale entry: {}", book_id);
+                    tracing::debug!("Stale entry: {}", book_id);
                     if *status == QueueStatus::Done {
-                        log::debug!("Marking stale entry as DONE: {}", book_id);
+                        tracing::debug!("Marking stale entry as DONE: {}", book_id);
                         to_remove.push(book_id.clone());
                     }
                 }
@@ -217,7 +326,7 @@ impl BookQueue {
 
         // Remove stale entries
         for book_id in to_remove {
-            log::debug!("Removing stale entry: {}", book_id);
+            tracing::debug!("Removing stale entry: {}", book_id);
             data.status.remove(&book_id);
             data.status_timestamps.remove(&book_id);
             data.book_data.remove(&book_id);
@@ -226,27 +335,29 @@ impl BookQueue {
     }
 
     /// Public refresh method: lock and delegate.
+    #[allow(dead_code)]
     pub fn refresh(&self) {
         let mut data = self.data.lock().unwrap();
-        Self::refresh_internal(&mut data);
+        self.refresh_internal(&mut data);
     }
 
-    /// Change the status timeout in hours.
-    pub fn set_status_timeout(&self, hours: u64) {
+    /// Change how long finished entries are kept.
+    pub fn set_status_timeout(&self, timeout: Duration) {
         let mut data = self.data.lock().unwrap();
-        data.status_timeout = Duration::from_secs(hours * 3600);
+        data.status_timeout = timeout;
     }
 }
 
-/// A global, lazily initialized instance of BookQueue (thread-safe by design).
-pub static BOOK_QUEUE: Lazy<BookQueue> = Lazy::new(BookQueue::new);
-
 #[cfg(test)]
 mod tests {
     use std::sync::Arc;
 
     use super::*;
 
+    fn test_queue() -> BookQueue {
+        BookQueue::new(Duration::from_secs(3600), std::env::temp_dir())
+    }
+
     #[test]
     fn test_book_queue_status_to_string() {
         assert_eq!(QueueStatus::Queued.to_string(), "queued");
@@ -258,7 +369,7 @@ mod tests {
 
     #[test]
     fn test_book_queue() {
-        let queue = BookQueue::new();
+        let queue = test_queue();
         let book_id = "ABCD";
         let status = QueueStatus::Done;
         // Test enqueue
@@ -266,7 +377,7 @@ mod tests {
         // Test update
         queue.update_status(book_id, status);
         // Change the clock to simulate a timeout
-        queue.set_status_timeout(0);
+        queue.set_status_timeout(Duration::ZERO);
         queue.refresh();
         // Check if the queue is empty
         assert_eq!(queue.get_next(), None);
@@ -274,7 +385,7 @@ mod tests {
 
     #[test]
     fn test_book_queue_status() {
-        let queue = BookQueue::new();
+        let queue = test_queue();
         let book_id = "ABCD";
         // Test enqueue
         queue.add(book_id, BookInfo::new(book_id, "Title"));
@@ -291,20 +402,20 @@ mod tests {
     #[test]
     // Test updating a non-existent book status
     fn test_book_queue_status_non_existent() {
-        let queue = BookQueue::new();
+        let queue = test_queue();
         let book_id = "ABCD";
         queue.update_status(book_id, QueueStatus::Downloading);
         queue.refresh();
         assert!(queue
             .get_status()
             .get(&QueueStatus::Downloading)
-            .is_some_and(|v| v.len() == 0));
+            .is_some_and(|v| v.is_empty()));
     }
 
     // Test thread safety
     #[test]
     fn test_book_queue_threadsafe() {
-        let queue = Arc::new(BookQueue::new());
+        let queue = Arc::new(test_queue());
         let book_id = "ABCD";
         let handles = (0..100)
             .map(|i| {
@@ -330,10 +441,10 @@ mod tests {
     proptest! {
         #[test]
         fn test_book_queue_proptest(status in any::<QueueStatus>()) {
-            let queue = BookQueue::new();
+            let queue = test_queue();
             let book_id = "ABCD";
-            queue.add(&book_id, BookInfo::new(&book_id, "Title"));
-            queue.update_status(&book_id, status.clone());
+            queue.add(book_id, BookInfo::new(book_id, "Title"));
+            queue.update_status(book_id, status.clone());
             if status == QueueStatus::Available {
                 prop_assert!(queue.get_status().get(&QueueStatus::Done).is_some_and(|v| v.len() == 1));
             } else {
@@ -343,10 +454,10 @@ mod tests {
 
         #[test]
         fn test_book_queue_proptest_refresh(status in any::<QueueStatus>()) {
-            let queue = BookQueue::new();
+            let queue = test_queue();
             let book_id = "ABCD";
-            queue.add(&book_id, BookInfo::new(&book_id, "Title"));
-            queue.update_status(&book_id, status.clone());
+            queue.add(book_id, BookInfo::new(book_id, "Title"));
+            queue.update_status(book_id, status.clone());
             queue.refresh();
             if status == QueueStatus::Available {
                 prop_assert!(queue.get_status().get(&QueueStatus::Done).is_some_and(|v| v.len() == 1));
@@ -357,7 +468,7 @@ mod tests {
 
         #[test]
         fn test_book_queue_proptest_threadsafe(count in 0usize..100) {
-            let queue = Arc::new(BookQueue::new());
+            let queue = Arc::new(test_queue());
             let book_id = "ABCD";
             let handles = (0..count).map(|i| {
                 let queue_ref = Arc::clone(&queue);
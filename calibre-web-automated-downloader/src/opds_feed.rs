@@ -0,0 +1,726 @@
+use crate::app::AppError;
+use crate::book_manager;
+use crate::covers::CoverSize;
+use crate::languages;
+use crate::models::{BookInfo, QueueEntry, QueueStatus};
+use crate::opds;
+use crate::search::SearchQuery;
+use crate::state::AppState;
+use axum::extract::{Query, State};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+use urlencoding::encode;
+
+const NAVIGATION: &str = "application/atom+xml;profile=opds-catalog;kind=navigation";
+const ACQUISITION: &str = "application/atom+xml;profile=opds-catalog;kind=acquisition";
+const ENTRY: &str = "application/atom+xml;type=entry;profile=opds-catalog";
+const OPENSEARCH: &str = "application/opensearchdescription+xml";
+
+/// Query parameters of the OPDS feeds and endpoints.
+#[derive(Debug, Default, Deserialize)]
+pub struct OpdsParams {
+    /// Search terms, for `/opds/search`.
+    #[serde(default)]
+    pub q: String,
+    #[serde(default)]
+    pub page: Option<u32>,
+    /// Author name, for `/opds/author`.
+    #[serde(default)]
+    pub name: String,
+    /// Language code, for `/opds/language`.
+    #[serde(default)]
+    pub code: String,
+    /// Book id, for `/opds/request` and `/opds/file`.
+    #[serde(default)]
+    pub id: String,
+}
+
+/// Where the feeds are served, e.g. `/books/opds`.
+fn base(state: &AppState) -> String {
+    format!("{}/opds", state.config().url_base)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Format `time` as an RFC 3339 UTC timestamp, as Atom requires.
+fn rfc3339(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rest) = ((secs / 86400) as i64, secs % 86400);
+    // Civil date from days since 1970-01-01, after Howard Hinnant's `civil_from_days`
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// How a book in a feed can be fetched.
+#[derive(Debug, Clone, PartialEq)]
+enum Acquisition {
+    /// Its file is in the ingest directory, in the given format.
+    File(String),
+    /// It can be queued for download.
+    Request,
+    /// It was ingested and its file is gone.
+    None,
+}
+
+fn link(rel: &str, kind: &str, href: &str) -> String {
+    format!(
+        r#"<link rel="{}" type="{}" href="{}"/>"#,
+        rel,
+        kind,
+        escape(href)
+    )
+}
+
+fn book_entry(
+    state: &AppState,
+    book: &BookInfo,
+    updated: SystemTime,
+    acquisition: Acquisition,
+) -> String {
+    let base = base(state);
+    let mut entry = format!(
+        "<entry><id>urn:cwa-book-downloader:{}</id><title>{}</title><updated>{}</updated>",
+        encode(&book.id),
+        escape(&book.title),
+        rfc3339(updated)
+    );
+    for author in &book.authors {
+        entry.push_str(&format!("<author><name>{}</name></author>", escape(author)));
+    }
+    let fields = [
+        ("dc:language", &book.language),
+        ("dc:publisher", &book.publisher),
+        ("dc:issued", &book.year),
+    ];
+    for (element, value) in fields {
+        if let Some(value) = value {
+            entry.push_str(&format!("<{0}>{1}</{0}>", element, escape(value)));
+        }
+    }
+    for isbn in &book.metadata.isbns {
+        entry.push_str(&format!(
+            "<dc:identifier>urn:isbn:{}</dc:identifier>",
+            isbn.isbn_13
+        ));
+    }
+    if let Some(description) = &book.metadata.description {
+        entry.push_str(&format!("<summary>{}</summary>", escape(description)));
+    }
+    // Covers go through /api/cover, like in the web interface. Their type is only
+    // known once fetched, so the links leave it out
+    if let Some(preview) = &book.preview {
+        let url_base = &state.config().url_base;
+        let images = [
+            ("http://opds-spec.org/image", CoverSize::Full),
+            ("http://opds-spec.org/image/thumbnail", CoverSize::Thumbnail),
+        ];
+        for (rel, size) in images {
+            let href = state
+                .covers
+                .proxy_url(url_base, &book.id, preview, size)
+                .unwrap_or_else(|| preview.clone());
+            entry.push_str(&format!(
+                r#"<link rel="{}" href="{}"/>"#,
+                rel,
+                escape(&href)
+            ));
+        }
+    }
+    match acquisition {
+        Acquisition::File(format) => entry.push_str(&link(
+            "http://opds-spec.org/acquisition",
+            opds::media_type(&format),
+            &format!("{}/file?id={}", base, encode(&book.id)),
+        )),
+        Acquisition::Request => entry.push_str(&link(
+            "http://opds-spec.org/acquisition/borrow",
+            ENTRY,
+            &format!("{}/request?id={}", base, encode(&book.id)),
+        )),
+        Acquisition::None => {}
+    }
+    entry.push_str("</entry>");
+    entry
+}
+
+fn navigation_entry(id: &str, title: &str, content: &str, href: &str) -> String {
+    format!(
+        "<entry><id>urn:cwa-book-downloader:{}</id><title>{}</title><updated>{}</updated>\
+         <content type=\"text\">{}</content>{}</entry>",
+        escape(id),
+        escape(title),
+        rfc3339(SystemTime::now()),
+        escape(content),
+        link("subsection", NAVIGATION, href)
+    )
+}
+
+/// A complete feed served at `base` + `path`.
+fn feed(
+    base: &str,
+    path: &str,
+    title: &str,
+    kind: &str,
+    links: &str,
+    entries: &[String],
+) -> Response {
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<feed xmlns="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/terms/" xmlns:opds="http://opds-spec.org/2010/catalog" xmlns:opensearch="http://a9.com/-/spec/opensearch/1.1/">
+<id>urn:cwa-book-downloader:feed:{}</id><title>{}</title><updated>{}</updated>
+{}{}{}{}
+{}
+</feed>
+"#,
+        escape(path),
+        escape(title),
+        rfc3339(SystemTime::now()),
+        link("self", kind, &format!("{}{}", base, path)),
+        link("start", NAVIGATION, base),
+        link("search", OPENSEARCH, &format!("{}/search.xml", base)),
+        links,
+        entries.join("\n")
+    );
+    ([(header::CONTENT_TYPE, kind)], body).into_response()
+}
+
+/// Books that finished downloading, most recent first.
+fn downloaded(state: &AppState) -> Vec<QueueEntry> {
+    state
+        .queue
+        .history()
+        .into_iter()
+        .filter(|entry| matches!(entry.status, QueueStatus::Available | QueueStatus::Done))
+        .collect()
+}
+
+fn acquisition(state: &AppState, book_id: &str) -> Acquisition {
+    match state.queue.ingest_path(book_id).exists() {
+        true => Acquisition::File(state.queue.format(book_id)),
+        false => Acquisition::None,
+    }
+}
+
+fn history_feed(
+    state: &AppState,
+    path: &str,
+    title: &str,
+    keep: impl Fn(&BookInfo) -> bool,
+) -> Response {
+    let base = base(state);
+    let entries = downloaded(state)
+        .into_iter()
+        .filter(|entry| keep(&entry.book))
+        .map(|entry| {
+            let acquisition = match state.queue.ingest_path(&entry.id).exists() {
+                true => Acquisition::File(entry.format),
+                false => Acquisition::None,
+            };
+            book_entry(state, &entry.book, entry.updated, acquisition)
+        })
+        .collect::<Vec<_>>();
+    feed(&base, path, title, ACQUISITION, "", &entries)
+}
+
+/// The catalogue root, linking to the other feeds.
+pub async fn handler_opds_root(State(state): State<AppState>) -> Response {
+    let base = base(&state);
+    let entries = [
+        navigation_entry(
+            "recent",
+            "Recent downloads",
+            "Books downloaded most recently",
+            &format!("{}/recent", base),
+        ),
+        navigation_entry(
+            "authors",
+            "By author",
+            "Downloaded books by author",
+            &format!("{}/authors", base),
+        ),
+        navigation_entry(
+            "languages",
+            "By language",
+            "Downloaded books by language",
+            &format!("{}/languages", base),
+        ),
+    ];
+    feed(&base, "", "Book Downloader", NAVIGATION, "", &entries)
+}
+
+pub async fn handler_opds_recent(State(state): State<AppState>) -> Response {
+    history_feed(&state, "/recent", "Recent downloads", |_| true)
+}
+
+pub async fn handler_opds_authors(State(state): State<AppState>) -> Response {
+    let base = base(&state);
+    let mut authors = BTreeMap::<String, usize>::new();
+    for entry in downloaded(&state) {
+        for author in entry.book.authors {
+            *authors.entry(author).or_default() += 1;
+        }
+    }
+    let entries = authors
+        .iter()
+        .map(|(author, count)| {
+            navigation_entry(
+                &format!("author:{}", encode(author)),
+                author,
+                &format!("{} books", count),
+                &format!("{}/author?name={}", base, encode(author)),
+            )
+        })
+        .collect::<Vec<_>>();
+    feed(&base, "/authors", "By author", NAVIGATION, "", &entries)
+}
+
+pub async fn handler_opds_author(
+    State(state): State<AppState>,
+    Query(params): Query<OpdsParams>,
+) -> Response {
+    let path = format!("/author?name={}", encode(&params.name));
+    history_feed(&state, &path, &params.name, |book| {
+        book.authors.contains(&params.name)
+    })
+}
+
+pub async fn handler_opds_languages(State(state): State<AppState>) -> Response {
+    let base = base(&state);
+    let mut codes = BTreeMap::<String, usize>::new();
+    for entry in downloaded(&state) {
+        if let Some(language) = entry.book.language {
+            *codes.entry(language).or_default() += 1;
+        }
+    }
+    let entries = codes
+        .iter()
+        .map(|(code, count)| {
+            let name = languages::by_code(code).map_or(code.as_str(), |l| l.name);
+            navigation_entry(
+                &format!("language:{}", encode(code)),
+                name,
+                &format!("{} books", count),
+                &format!("{}/language?code={}", base, encode(code)),
+            )
+        })
+        .collect::<Vec<_>>();
+    feed(&base, "/languages", "By language", NAVIGATION, "", &entries)
+}
+
+pub async fn handler_opds_language(
+    State(state): State<AppState>,
+    Query(params): Query<OpdsParams>,
+) -> Response {
+    let path = format!("/language?code={}", encode(&params.code));
+    let title = languages::by_code(&params.code).map_or(params.code.as_str(), |l| l.name);
+    history_feed(&state, &path, title, |book| {
+        book.language.as_deref() == Some(params.code.as_str())
+    })
+}
+
+/// The OpenSearch description pointing e-readers at `/opds/search`.
+pub async fn handler_opds_search_description(State(state): State<AppState>) -> Response {
+    let base = base(&state);
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<OpenSearchDescription xmlns="http://a9.com/-/spec/opensearch/1.1/">
+<ShortName>Book Downloader</ShortName>
+<Description>Search for books to download</Description>
+<Url type="{}" template="{}/search?q={{searchTerms}}&amp;page={{startPage?}}"/>
+</OpenSearchDescription>
+"#,
+        ACQUISITION,
+        escape(&base)
+    );
+    ([(header::CONTENT_TYPE, OPENSEARCH)], body).into_response()
+}
+
+/// Search the enabled sources. Books already downloaded link to their file, the
+/// others to `/opds/request`, which queues them.
+pub async fn handler_opds_search(
+    State(state): State<AppState>,
+    Query(params): Query<OpdsParams>,
+) -> Result<Response, AppError> {
+    let base = base(&state);
+    let query = SearchQuery {
+        page: params.page.unwrap_or(1).max(1),
+        ..SearchQuery::new(&params.q)
+    };
+    let results = match query.text.trim().is_empty() {
+        true => Default::default(),
+        false => state.sources.search(&state, &query, false).await?,
+    };
+    let now = SystemTime::now();
+    let entries = results
+        .results
+        .iter()
+        .map(|book| {
+            let acquisition = match acquisition(&state, &book.id) {
+                file @ Acquisition::File(_) => file,
+                _ => Acquisition::Request,
+            };
+            book_entry(&state, book, now, acquisition)
+        })
+        .collect::<Vec<_>>();
+    let mut links = String::new();
+    if results.has_more {
+        links.push_str(&link(
+            "next",
+            ACQUISITION,
+            &format!(
+                "{}/search?q={}&page={}",
+                base,
+                encode(&params.q),
+                query.page + 1
+            ),
+        ));
+    }
+    if let Some(total) = results.total {
+        links.push_str(&format!(
+            "<opensearch:totalResults>{}</opensearch:totalResults>",
+            total
+        ));
+    }
+    let path = format!("/search?q={}&page={}", encode(&params.q), query.page);
+    Ok(feed(&base, &path, &params.q, ACQUISITION, &links, &entries))
+}
+
+/// Queue a book for download, and answer with its entry. Books already owned are
+/// checked as by `/api/download`, so with `DUPLICATE_CHECK=refuse` they fail with 409.
+pub async fn handler_opds_request(
+    State(state): State<AppState>,
+    Query(params): Query<OpdsParams>,
+) -> Result<Response, AppError> {
+    let book = match state.queue.get_book(&params.id) {
+        Some(book) => book,
+        None => {
+            let book = state.sources.get_info(&state, &params.id, false).await?;
+            let duplicate = state.library.check_download(&state, &book, false).await?;
+            if let Some(duplicate) = &duplicate {
+                tracing::warn!(
+                    "Queueing {}, which is already owned: {:?}",
+                    params.id,
+                    duplicate
+                );
+            }
+            book_manager::queue_book(&state, &params.id, book.clone());
+            book
+        }
+    };
+    let entry = book_entry(
+        &state,
+        &book,
+        SystemTime::now(),
+        acquisition(&state, &params.id),
+    );
+    let body = format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+{}"#,
+        entry.replacen(
+            "<entry>",
+            r#"<entry xmlns="http://www.w3.org/2005/Atom" xmlns:dc="http://purl.org/dc/terms/">"#,
+            1
+        )
+    );
+    Ok(([(header::CONTENT_TYPE, ENTRY)], body).into_response())
+}
+
+/// Serve a downloaded book from the ingest directory.
+pub async fn handler_opds_file(
+    State(state): State<AppState>,
+    Query(params): Query<OpdsParams>,
+) -> Result<Response, AppError> {
+    let path = state.queue.ingest_path(&params.id);
+    let data = match tokio::fs::read(&path).await {
+        Ok(data) => data,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(StatusCode::NOT_FOUND.into_response())
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let format = state.queue.format(&params.id);
+    let name = state
+        .queue
+        .get_book(&params.id)
+        .map(|book| book.title)
+        .unwrap_or_else(|| params.id.clone())
+        .replace(|c: char| c.is_control() || c == '"' || c == '\\', "");
+    let disposition = format!("attachment; filename=\"{}.{}\"", name, format);
+    Ok((
+        [
+            (header::CONTENT_TYPE, opds::media_type(&format).to_string()),
+            (header::CONTENT_DISPOSITION, disposition),
+        ],
+        data,
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sources::tests::source;
+    use crate::sources::Sources;
+    use crate::state::test_state;
+    use axum::body::to_bytes;
+    use std::time::Duration;
+    use tokio::test;
+
+    async fn body(response: Response) -> String {
+        let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    /// Titles of the entries of an Atom feed.
+    fn titles(feed: &str) -> Vec<String> {
+        let document = roxmltree::Document::parse(feed).unwrap();
+        document
+            .root_element()
+            .children()
+            .filter(|node| node.has_tag_name("entry"))
+            .filter_map(|entry| {
+                entry
+                    .children()
+                    .find(|child| child.has_tag_name("title"))?
+                    .text()
+                    .map(str::to_string)
+            })
+            .collect()
+    }
+
+    fn downloaded_book(state: &AppState, id: &str, title: &str, author: &str, language: &str) {
+        let mut book = BookInfo::new(id, title);
+        book.authors = vec![author.to_string()];
+        book.language = Some(language.to_string());
+        state.queue.add(id, book);
+        state.queue.update_status(id, QueueStatus::Available);
+        std::fs::write(state.queue.ingest_path(id), "epub data").unwrap();
+    }
+
+    #[test]
+    async fn test_rfc3339() {
+        assert_eq!(rfc3339(UNIX_EPOCH), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            rfc3339(UNIX_EPOCH + Duration::from_secs(951_827_696)),
+            "2000-02-29T12:34:56Z"
+        );
+    }
+
+    #[test]
+    async fn test_feeds_list_downloaded_books() {
+        let state = test_state(&[]);
+        downloaded_book(&state, "a1", "Dune", "Frank Herbert", "en");
+        downloaded_book(
+            &state,
+            "opds:urn:uuid:2",
+            "Le Petit Prince",
+            "Antoine de Saint-Exupéry",
+            "fr",
+        );
+        state.queue.add("q", BookInfo::new("q", "Still queued"));
+
+        let recent = body(handler_opds_recent(State(state.clone())).await).await;
+        let mut recent_titles = titles(&recent);
+        recent_titles.sort();
+        assert_eq!(recent_titles, vec!["Dune", "Le Petit Prince"]);
+        assert!(recent.contains(r#"href="/opds/file?id=opds%3Aurn%3Auuid%3A2""#));
+
+        let authors = body(handler_opds_authors(State(state.clone())).await).await;
+        assert_eq!(
+            titles(&authors),
+            vec!["Antoine de Saint-Exupéry", "Frank Herbert"]
+        );
+        let params = OpdsParams {
+            name: "Frank Herbert".to_string(),
+            ..Default::default()
+        };
+        let author = body(handler_opds_author(State(state.clone()), Query(params)).await).await;
+        assert_eq!(titles(&author), vec!["Dune"]);
+
+        let languages = body(handler_opds_languages(State(state.clone())).await).await;
+        assert_eq!(titles(&languages), vec!["English", "French"]);
+        let params = OpdsParams {
+            code: "fr".to_string(),
+            ..Default::default()
+        };
+        let language = body(handler_opds_language(State(state.clone()), Query(params)).await).await;
+        assert_eq!(titles(&language), vec!["Le Petit Prince"]);
+    }
+
+    #[test]
+    async fn test_file_is_served_from_the_ingest_directory() {
+        let state = test_state(&[]);
+        downloaded_book(&state, "opds:urn:uuid:2", "Le \"Petit\" Prince", "A", "fr");
+        let params = OpdsParams {
+            id: "opds:urn:uuid:2".to_string(),
+            ..Default::default()
+        };
+        let response = handler_opds_file(State(state.clone()), Query(params))
+            .await
+            .into_response();
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"Le Petit Prince.epub\""
+        );
+        assert_eq!(body(response).await, "epub data");
+
+        let params = OpdsParams {
+            id: "../../etc/passwd".to_string(),
+            ..Default::default()
+        };
+        let response = handler_opds_file(State(state), Query(params))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    async fn test_files_are_served_in_their_own_format() {
+        let state = test_state(&[]);
+        let mut book = BookInfo::new("a1", "Dune");
+        book.format = Some("pdf".to_string());
+        state.queue.add("a1", book);
+        state.queue.update_status("a1", QueueStatus::Available);
+        let path = state.queue.ingest_path("a1");
+        assert_eq!(path.file_name().unwrap(), "a1.pdf");
+        std::fs::write(path, "pdf data").unwrap();
+
+        let recent = body(handler_opds_recent(State(state.clone())).await).await;
+        assert!(recent.contains(r#"type="application/pdf" href="/opds/file?id=a1""#));
+
+        let params = OpdsParams {
+            id: "a1".to_string(),
+            ..Default::default()
+        };
+        let response = handler_opds_file(State(state), Query(params))
+            .await
+            .into_response();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], "application/pdf");
+        assert_eq!(
+            response.headers()[header::CONTENT_DISPOSITION],
+            "attachment; filename=\"Dune.pdf\""
+        );
+        assert_eq!(body(response).await, "pdf data");
+    }
+
+    #[test]
+    async fn test_search_links_to_files_or_requests() {
+        let mut state = test_state(&[]);
+        let books = vec![
+            BookInfo::new("a1", "Dune"),
+            BookInfo::new("a2", "Dune Messiah"),
+        ];
+        state.sources = Sources::new(vec![source("annas_archive", books)]);
+        downloaded_book(&state, "a1", "Dune", "Frank Herbert", "en");
+
+        let params = OpdsParams {
+            q: "dune".to_string(),
+            ..Default::default()
+        };
+        let results = handler_opds_search(State(state.clone()), Query(params))
+            .await
+            .into_response();
+        let results = body(results).await;
+        assert_eq!(titles(&results), vec!["Dune", "Dune Messiah"]);
+        assert!(results.contains(r#"href="/opds/file?id=a1""#));
+        assert!(results.contains(r#"href="/opds/request?id=a2""#));
+
+        let params = OpdsParams {
+            id: "a2".to_string(),
+            ..Default::default()
+        };
+        let entry = handler_opds_request(State(state.clone()), Query(params))
+            .await
+            .into_response();
+        assert_eq!(entry.status(), StatusCode::OK);
+        assert_eq!(state.queue.get_book("a2").unwrap().title, "Dune Messiah");
+    }
+
+    #[test]
+    async fn test_request_refuses_owned_books() {
+        let mut state = test_state(&[("DUPLICATE_CHECK", "refuse")]);
+        let mut book = BookInfo::new("a2", "Dune");
+        book.authors = vec!["Frank Herbert".to_string()];
+        state.sources = Sources::new(vec![source("annas_archive", vec![book])]);
+        downloaded_book(&state, "a1", "Dune", "Frank Herbert", "en");
+
+        let params = OpdsParams {
+            id: "a2".to_string(),
+            ..Default::default()
+        };
+        let response = handler_opds_request(State(state.clone()), Query(params))
+            .await
+            .into_response();
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+        assert!(state.queue.get_book("a2").is_none());
+    }
+
+    #[test]
+    async fn test_covers_link_through_the_proxy() {
+        let mut state = test_state(&[("URL_BASE", "/books")]);
+        let mut book = BookInfo::new("a1", "Dune");
+        book.preview = Some("https://covers.example/dune.jpg".to_string());
+        state.sources = Sources::new(vec![source("annas_archive", vec![book])]);
+
+        let params = OpdsParams {
+            q: "dune".to_string(),
+            ..Default::default()
+        };
+        let results = handler_opds_search(State(state.clone()), Query(params))
+            .await
+            .into_response();
+        let results = body(results).await;
+        assert!(results
+            .contains(r#"<link rel="http://opds-spec.org/image" href="/books/api/cover?id=a1"/>"#));
+        assert!(results.contains(r#"href="/books/api/cover?id=a1&amp;size=thumbnail""#));
+        assert!(!results.contains("covers.example"));
+        assert_eq!(
+            state.covers.url_of("a1").as_deref(),
+            Some("https://covers.example/dune.jpg")
+        );
+    }
+
+    #[test]
+    async fn test_search_description_and_escaping() {
+        let state = test_state(&[("URL_BASE", "/books")]);
+        let description = body(handler_opds_search_description(State(state.clone())).await).await;
+        let document = roxmltree::Document::parse(&description).unwrap();
+        let url = document
+            .descendants()
+            .find(|node| node.has_tag_name("Url"))
+            .unwrap();
+        assert_eq!(
+            url.attribute("template"),
+            Some("/books/opds/search?q={searchTerms}&page={startPage?}")
+        );
+
+        downloaded_book(&state, "a1", "Tom & Jerry <3", "A", "en");
+        let recent = body(handler_opds_recent(State(state)).await).await;
+        assert_eq!(titles(&recent), vec!["Tom & Jerry <3"]);
+    }
+}
@@ -0,0 +1,176 @@
+use crate::config::{Config, ConfigError, LoadOptions, RELOADABLE_SETTINGS};
+use crate::state::AppState;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+/// How often the config file is checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// What a reload changed.
+#[derive(Debug, Default, PartialEq)]
+pub struct ReloadOutcome {
+    /// Settings whose new values are now in effect.
+    pub applied: Vec<&'static str>,
+    /// Settings that changed but only take effect after a restart.
+    pub restart_required: Vec<&'static str>,
+}
+
+/// Load the configuration again and apply the settings that are safe to change live.
+///
+/// Searches and downloads started afterwards use the new values; ones already
+/// running finish with the values they started with.
+pub fn reload(state: &AppState, options: &LoadOptions) -> Result<ReloadOutcome, ConfigError> {
+    apply(state, Config::load(options))
+}
+
+/// Apply the settings of a freshly `loaded` configuration that are safe to change
+/// live, unless it failed to load.
+fn apply(
+    state: &AppState,
+    loaded: Result<Config, ConfigError>,
+) -> Result<ReloadOutcome, ConfigError> {
+    let new = loaded?;
+    let current = state.config();
+
+    let mut outcome = ReloadOutcome::default();
+    for setting in &current.resolved {
+        let changed = new
+            .resolved
+            .iter()
+            .find(|s| s.name == setting.name)
+            .is_some_and(|s| s.value != setting.value);
+        if !changed {
+            continue;
+        }
+        if RELOADABLE_SETTINGS.contains(&setting.name) {
+            outcome.applied.push(setting.name);
+        } else {
+            outcome.restart_required.push(setting.name);
+        }
+    }
+
+    let config = current.with_reloadable(&new);
+    config.warn_rejected_languages();
+    state
+        .queue
+        .set_status_timeout(Duration::from_secs(config.status_timeout));
+    state.set_config(config);
+    Ok(outcome)
+}
+
+/// Reload and log the outcome; an invalid configuration keeps the current one.
+fn reload_and_log(state: &AppState, options: &LoadOptions) {
+    match reload(state, options) {
+        Ok(outcome) => {
+            tracing::info!(applied = ?outcome.applied, "Configuration reloaded");
+            if !outcome.restart_required.is_empty() {
+                tracing::warn!(
+                    settings = ?outcome.restart_required,
+                    "Changed settings only take effect after a restart"
+                );
+            }
+        }
+        Err(e) => tracing::error!("Keeping the current configuration: {}", e),
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reload the configuration on SIGHUP and whenever the config file changes.
+pub fn spawn_reloader(state: AppState, options: LoadOptions) {
+    #[cfg(unix)]
+    {
+        let state = state.clone();
+        let options = options.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(e) => {
+                    tracing::warn!("Cannot listen for SIGHUP: {}", e);
+                    return;
+                }
+            };
+            while hangup.recv().await.is_some() {
+                tracing::info!("SIGHUP received, reloading configuration");
+                reload_and_log(&state, &options);
+            }
+        });
+    }
+
+    let Some(path) = state.config().config_file.clone() else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut last_modified = modified(&path);
+        let mut ticker = tokio::time::interval(WATCH_INTERVAL);
+        loop {
+            ticker.tick().await;
+            let current = modified(&path);
+            if current != last_modified {
+                last_modified = current;
+                tracing::info!(path = %path.display(), "Config file changed, reloading");
+                reload_and_log(&state, &options);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::test_state;
+
+    /// Load the config file of `options` as `reload` does, without reading the
+    /// process environment or `.env`.
+    fn load(options: &LoadOptions) -> Result<Config, ConfigError> {
+        Config::load_with_env(options, |_| None)
+    }
+
+    #[test]
+    fn test_reload_applies_only_reloadable_settings() {
+        let state = test_state(&[]);
+        let initial = state.config();
+        let path = initial.tmp_dir.join("config.toml");
+        std::fs::write(
+            &path,
+            "max_retry = 7\nbook_language = \"de\"\nflask_port = 9999\n",
+        )
+        .unwrap();
+
+        let options = LoadOptions {
+            config_path: Some(path),
+            ..Default::default()
+        };
+        let outcome = apply(&state, load(&options)).unwrap();
+
+        assert!(outcome.applied.contains(&"MAX_RETRY"));
+        assert!(outcome.applied.contains(&"BOOK_LANGUAGE"));
+        assert!(outcome.restart_required.contains(&"FLASK_PORT"));
+        assert!(outcome.restart_required.contains(&"TMP_DIR"));
+
+        let config = state.config();
+        assert_eq!(config.max_retry, 7);
+        assert_eq!(config.book_language, vec!["de"]);
+        assert_eq!(config.flask_port, initial.flask_port);
+        assert_eq!(config.tmp_dir, initial.tmp_dir);
+        // The snapshot taken before the reload is left untouched
+        assert_eq!(initial.max_retry, 3);
+    }
+
+    #[test]
+    fn test_invalid_reload_keeps_current_config() {
+        let state = test_state(&[]);
+        let path = state.config().tmp_dir.join("config.toml");
+        std::fs::write(&path, "max_retry = \"many\"\n").unwrap();
+
+        let options = LoadOptions {
+            config_path: Some(path),
+            ..Default::default()
+        };
+        assert!(apply(&state, load(&options)).is_err());
+        assert_eq!(state.config().max_retry, 3);
+    }
+}
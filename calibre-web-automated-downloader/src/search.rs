@@ -0,0 +1,609 @@
+use crate::config::Config;
+use crate::languages;
+use crate::models::BookInfo;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use urlencoding::encode;
+
+/// A search filter that could not be understood.
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    /// The request parameter the value was given in.
+    pub parameter: &'static str,
+    pub value: String,
+    pub message: String,
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid {} {:?}: {}",
+            self.parameter, self.value, self.message
+        )
+    }
+}
+
+impl std::error::Error for QueryError {}
+
+fn invalid(parameter: &'static str, value: &str, message: &str) -> QueryError {
+    QueryError {
+        parameter,
+        value: value.to_string(),
+        message: message.to_string(),
+    }
+}
+
+/// Kinds of content Anna's Archive can restrict a search to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentType {
+    BookNonfiction,
+    BookFiction,
+    BookUnknown,
+    BookComic,
+    Magazine,
+    StandardsDocument,
+    MusicalScore,
+    Other,
+}
+
+impl ContentType {
+    const ALL: [ContentType; 8] = [
+        ContentType::BookNonfiction,
+        ContentType::BookFiction,
+        ContentType::BookUnknown,
+        ContentType::BookComic,
+        ContentType::Magazine,
+        ContentType::StandardsDocument,
+        ContentType::MusicalScore,
+        ContentType::Other,
+    ];
+
+    /// The value of Anna's Archive's `content` parameter.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ContentType::BookNonfiction => "book_nonfiction",
+            ContentType::BookFiction => "book_fiction",
+            ContentType::BookUnknown => "book_unknown",
+            ContentType::BookComic => "book_comic",
+            ContentType::Magazine => "magazine",
+            ContentType::StandardsDocument => "standards_document",
+            ContentType::MusicalScore => "musical_score",
+            ContentType::Other => "other",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|content| content.as_str() == value)
+    }
+}
+
+/// Orders Anna's Archive can sort results in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    #[default]
+    Relevance,
+    Newest,
+    Oldest,
+    Largest,
+    Smallest,
+    NewestAdded,
+    OldestAdded,
+}
+
+impl SortOrder {
+    const ALL: [SortOrder; 7] = [
+        SortOrder::Relevance,
+        SortOrder::Newest,
+        SortOrder::Oldest,
+        SortOrder::Largest,
+        SortOrder::Smallest,
+        SortOrder::NewestAdded,
+        SortOrder::OldestAdded,
+    ];
+
+    /// The value of Anna's Archive's `sort` parameter; relevance is its default.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SortOrder::Relevance => "",
+            SortOrder::Newest => "newest",
+            SortOrder::Oldest => "oldest",
+            SortOrder::Largest => "largest",
+            SortOrder::Smallest => "smallest",
+            SortOrder::NewestAdded => "newest_added",
+            SortOrder::OldestAdded => "oldest_added",
+        }
+    }
+
+    fn parse(value: &str) -> Option<Self> {
+        if value == "relevance" {
+            return Some(SortOrder::Relevance);
+        }
+        Self::ALL.into_iter().find(|sort| sort.as_str() == value)
+    }
+}
+
+/// The raw filter parameters of `/api/search`; every one is optional.
+///
+/// Lists are comma separated, e.g. `format=epub,pdf`.
+#[derive(Debug, Default, Deserialize)]
+pub struct SearchFilters {
+    pub format: Option<String>,
+    pub language: Option<String>,
+    pub content: Option<String>,
+    pub year_from: Option<String>,
+    pub year_to: Option<String>,
+    pub sort: Option<String>,
+    /// A size such as `20MB` or `1.5 GB`, or a number of bytes.
+    pub max_size: Option<String>,
+    /// The page of results to return, from 1.
+    pub page: Option<String>,
+    /// Results per page, up to [`MAX_PAGE_SIZE`].
+    pub page_size: Option<String>,
+    /// Return this many pages at once, starting at `page`, up to [`MAX_PAGES`].
+    pub pages: Option<String>,
+}
+
+/// Rows Anna's Archive returns per search page.
+pub const AA_PAGE_SIZE: u32 = 100;
+/// Largest page size a search can ask for.
+pub const MAX_PAGE_SIZE: u32 = AA_PAGE_SIZE;
+/// Most pages a single search can fetch.
+pub const MAX_PAGES: u32 = 10;
+/// Most upstream pages read to fill the requested pages when results are filtered
+/// locally, counting from the first page.
+pub const MAX_SCANNED_PAGES: u32 = 20;
+/// Highest page a search can ask for.
+const MAX_PAGE: u32 = 1000;
+
+/// A search with its filters. Formats and languages left unset fall back to
+/// `SUPPORTED_FORMATS` and `BOOK_LANGUAGE`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchQuery {
+    pub text: String,
+    pub formats: Option<Vec<String>>,
+    /// Language codes, see [`languages::LANGUAGES`].
+    pub languages: Option<Vec<String>>,
+    pub content: Vec<ContentType>,
+    pub year_from: Option<u32>,
+    pub year_to: Option<u32>,
+    pub sort: SortOrder,
+    /// Largest file size to return, in bytes.
+    pub max_size: Option<u64>,
+    /// The first page to return, from 1.
+    pub page: u32,
+    pub page_size: u32,
+    /// How many consecutive pages to return as one.
+    pub pages: u32,
+}
+
+impl Default for SearchQuery {
+    fn default() -> Self {
+        SearchQuery {
+            text: String::new(),
+            formats: None,
+            languages: None,
+            content: Vec::new(),
+            year_from: None,
+            year_to: None,
+            sort: SortOrder::default(),
+            max_size: None,
+            page: 1,
+            page_size: AA_PAGE_SIZE,
+            pages: 1,
+        }
+    }
+}
+
+/// One or more pages of search results.
+#[derive(Debug, Default, Serialize)]
+pub struct SearchResults {
+    pub results: Vec<BookInfo>,
+    /// The first page returned.
+    pub page: u32,
+    pub page_size: u32,
+    /// Whether results exist after the returned pages.
+    pub has_more: bool,
+    /// The number of matches Anna's Archive reports, when it gives an exact count.
+    pub total: Option<u64>,
+}
+
+/// Split a comma separated parameter; blank parameters count as unset.
+fn list(value: &Option<String>) -> Option<Vec<&str>> {
+    let items = value
+        .as_deref()?
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .collect::<Vec<_>>();
+    (!items.is_empty()).then_some(items)
+}
+
+/// A whole number parameter between `min` and `max`.
+fn number(
+    parameter: &'static str,
+    value: &Option<String>,
+    min: u32,
+    max: u32,
+) -> Result<Option<u32>, QueryError> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => value
+            .parse::<u32>()
+            .ok()
+            .filter(|n| (min..=max).contains(n))
+            .map(Some)
+            .ok_or_else(|| {
+                invalid(
+                    parameter,
+                    value,
+                    &format!("expected a number from {} to {}", min, max),
+                )
+            }),
+    }
+}
+
+fn year(parameter: &'static str, value: &Option<String>) -> Result<Option<u32>, QueryError> {
+    match value.as_deref().map(str::trim) {
+        None | Some("") => Ok(None),
+        Some(value) => value
+            .parse::<u32>()
+            .ok()
+            .filter(|year| *year <= 9999)
+            .map(Some)
+            .ok_or_else(|| invalid(parameter, value, "expected a year such as 1999")),
+    }
+}
+
+/// Parse a size such as `1.5MB`, `20 kb` or `1048576`, with binary units.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+    let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1u64,
+        "kb" | "k" | "kib" => 1 << 10,
+        "mb" | "m" | "mib" => 1 << 20,
+        "gb" | "g" | "gib" => 1 << 30,
+        "tb" | "t" | "tib" => 1 << 40,
+        _ => return None,
+    };
+    let bytes = number * multiplier as f64;
+    (bytes.is_finite() && bytes <= u64::MAX as f64).then_some(bytes as u64)
+}
+
+/// The leading year of a value such as `2021` or `2021, 2nd edition`.
+fn leading_year(value: &str) -> Option<u32> {
+    let digits = value
+        .trim()
+        .chars()
+        .take_while(char::is_ascii_digit)
+        .collect::<String>();
+    digits.parse().ok()
+}
+
+impl SearchQuery {
+    /// A search for `text` with the configured defaults.
+    pub fn new(text: &str) -> Self {
+        SearchQuery {
+            text: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Validate the filters of a search for `text`.
+    pub fn from_filters(text: &str, filters: &SearchFilters) -> Result<Self, QueryError> {
+        let mut query = SearchQuery::new(text);
+
+        if let Some(formats) = list(&filters.format) {
+            let formats = formats
+                .into_iter()
+                .map(|format| {
+                    let valid =
+                        format.len() <= 10 && format.chars().all(|c| c.is_ascii_alphanumeric());
+                    if valid {
+                        Ok(format.to_ascii_lowercase())
+                    } else {
+                        Err(invalid(
+                            "format",
+                            format,
+                            "expected a file extension such as epub",
+                        ))
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+            query.formats = Some(formats);
+        }
+        if let Some(entries) = list(&filters.language) {
+            let mut codes = Vec::new();
+            for entry in entries {
+                let language =
+                    languages::parse(entry).map_err(|e| invalid("language", entry, &e.reason))?;
+                if !codes.iter().any(|code| code == language.code) {
+                    codes.push(language.code.to_string());
+                }
+            }
+            query.languages = Some(codes);
+        }
+        if let Some(contents) = list(&filters.content) {
+            query.content = contents
+                .into_iter()
+                .map(|content| {
+                    ContentType::parse(&content.to_ascii_lowercase()).ok_or_else(|| {
+                        invalid(
+                            "content",
+                            content,
+                            "expected one of book_nonfiction, book_fiction, book_unknown, book_comic, magazine, standards_document, musical_score, other",
+                        )
+                    })
+                })
+                .collect::<Result<_, _>>()?;
+        }
+
+        query.year_from = year("year_from", &filters.year_from)?;
+        query.year_to = year("year_to", &filters.year_to)?;
+        if let (Some(from), Some(to)) = (query.year_from, query.year_to) {
+            if from > to {
+                return Err(invalid(
+                    "year_to",
+                    &to.to_string(),
+                    "must not be before year_from",
+                ));
+            }
+        }
+
+        if let Some(sort) = filters
+            .sort
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            query.sort = SortOrder::parse(&sort.to_ascii_lowercase()).ok_or_else(|| {
+                invalid(
+                    "sort",
+                    sort,
+                    "expected one of relevance, newest, oldest, largest, smallest, newest_added, oldest_added",
+                )
+            })?;
+        }
+        if let Some(max_size) = filters
+            .max_size
+            .as_deref()
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+        {
+            query.max_size =
+                Some(parse_size(max_size).ok_or_else(|| {
+                    invalid("max_size", max_size, "expected a size such as 20MB")
+                })?);
+        }
+
+        query.page = number("page", &filters.page, 1, MAX_PAGE)?.unwrap_or(query.page);
+        query.page_size =
+            number("page_size", &filters.page_size, 1, MAX_PAGE_SIZE)?.unwrap_or(query.page_size);
+        query.pages = number("pages", &filters.pages, 1, MAX_PAGES)?.unwrap_or(query.pages);
+
+        Ok(query)
+    }
+
+    /// The Anna's Archive search path for page `page`, with every value URL-encoded.
+    pub fn to_path(&self, config: &Config, page: u32) -> String {
+        let mut path = format!(
+            "/search?index=&page={}&display=table&acc=aa_download&acc=external_download&sort={}",
+            page,
+            self.sort.as_str()
+        );
+        for content in &self.content {
+            path.push_str(&format!("&content={}", content.as_str()));
+        }
+        let formats = self.formats.as_ref().unwrap_or(&config.supported_formats);
+        for format in formats {
+            path.push_str(&format!("&ext={}", encode(format)));
+        }
+        let languages = self.languages.as_ref().unwrap_or(&config.book_language);
+        for language in languages {
+            path.push_str(&format!("&lang={}", encode(language)));
+        }
+        path.push_str(&format!("&q={}", encode(&self.text)));
+        path
+    }
+
+    /// The Anna's Archive pages holding the requested results, and the offset of the
+    /// first requested result in the first of them.
+    pub fn upstream_pages(&self) -> (std::ops::RangeInclusive<u32>, usize) {
+        let start = (self.page - 1) * self.page_size;
+        let end = start + self.page_size * self.pages;
+        let first = start / AA_PAGE_SIZE + 1;
+        let last = (end - 1) / AA_PAGE_SIZE + 1;
+        (first..=last, (start - (first - 1) * AA_PAGE_SIZE) as usize)
+    }
+
+    /// Whether some results may be dropped by [`SearchQuery::matches`], so pages
+    /// of results no longer line up with upstream pages.
+    pub fn filters_locally(&self) -> bool {
+        self.year_from.is_some() || self.year_to.is_some() || self.max_size.is_some()
+    }
+
+    /// How many matching results come before the requested pages, and how many
+    /// the requested pages hold.
+    pub fn window(&self) -> (usize, usize) {
+        let skipped = (self.page - 1) * self.page_size;
+        (skipped as usize, (self.page_size * self.pages) as usize)
+    }
+
+    /// Whether `book` passes the filters Anna's Archive cannot apply itself: the
+    /// year range and the maximum size. Books whose year or size is unknown are
+    /// left out when the corresponding filter is set.
+    pub fn matches(&self, book: &BookInfo) -> bool {
+        if self.year_from.is_some() || self.year_to.is_some() {
+            let Some(year) = book.year.as_deref().and_then(leading_year) else {
+                return false;
+            };
+            if self.year_from.is_some_and(|from| year < from)
+                || self.year_to.is_some_and(|to| year > to)
+            {
+                return false;
+            }
+        }
+        if let Some(max_size) = self.max_size {
+            match book.size.as_deref().and_then(parse_size) {
+                Some(size) if size <= max_size => {}
+                _ => return false,
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::test_config;
+
+    fn filters(pairs: &[(&str, &str)]) -> SearchFilters {
+        let get = |name: &str| {
+            pairs
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+        SearchFilters {
+            format: get("format"),
+            language: get("language"),
+            content: get("content"),
+            year_from: get("year_from"),
+            year_to: get("year_to"),
+            sort: get("sort"),
+            max_size: get("max_size"),
+            page: get("page"),
+            page_size: get("page_size"),
+            pages: get("pages"),
+        }
+    }
+
+    fn book(year: &str, size: &str) -> BookInfo {
+        BookInfo {
+            year: Some(year.to_string()),
+            size: Some(size.to_string()),
+            ..BookInfo::new("id", "title")
+        }
+    }
+
+    #[test]
+    fn test_defaults_come_from_config() {
+        let config = test_config(&[("SUPPORTED_FORMATS", "epub,mobi"), ("BOOK_LANGUAGE", "en")]);
+        let path = SearchQuery::new("rust & go").to_path(&config, 1);
+        assert_eq!(
+            path,
+            "/search?index=&page=1&display=table&acc=aa_download&acc=external_download&sort=&ext=epub&ext=mobi&lang=en&q=rust%20%26%20go"
+        );
+    }
+
+    #[test]
+    fn test_filters_override_defaults() {
+        let config = test_config(&[("SUPPORTED_FORMATS", "epub"), ("BOOK_LANGUAGE", "en")]);
+        let query = SearchQuery::from_filters(
+            "dune",
+            &filters(&[
+                ("format", "PDF, djvu"),
+                ("language", "japanese,zh-TW"),
+                ("content", "book_fiction"),
+                ("sort", "newest"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(
+            query.to_path(&config, 2),
+            "/search?index=&page=2&display=table&acc=aa_download&acc=external_download&sort=newest&content=book_fiction&ext=pdf&ext=djvu&lang=ja&lang=zh-Hant&q=dune"
+        );
+    }
+
+    #[test]
+    fn test_blank_filters_are_unset() {
+        let query = SearchQuery::from_filters(
+            "dune",
+            &filters(&[("format", ""), ("year_from", " "), ("sort", "")]),
+        )
+        .unwrap();
+        assert_eq!(query, SearchQuery::new("dune"));
+    }
+
+    #[test]
+    fn test_invalid_filters() {
+        for (parameter, value) in [
+            ("format", "ep&ub"),
+            ("language", "klingon"),
+            ("content", "novel"),
+            ("year_from", "last year"),
+            ("sort", "best"),
+            ("max_size", "big"),
+            ("page", "0"),
+            ("page_size", "101"),
+            ("pages", "11"),
+        ] {
+            let error =
+                SearchQuery::from_filters("dune", &filters(&[(parameter, value)])).unwrap_err();
+            assert_eq!(error.parameter, parameter);
+            assert_eq!(error.value, value);
+        }
+
+        let error = SearchQuery::from_filters(
+            "dune",
+            &filters(&[("year_from", "2000"), ("year_to", "1990")]),
+        )
+        .unwrap_err();
+        assert_eq!(error.parameter, "year_to");
+    }
+
+    #[test]
+    fn test_upstream_pages() {
+        let query = |page: u32, page_size: u32, pages: u32| SearchQuery {
+            page,
+            page_size,
+            pages,
+            ..SearchQuery::new("dune")
+        };
+        assert_eq!(SearchQuery::new("dune").upstream_pages(), (1..=1, 0));
+        assert_eq!(query(2, 100, 1).upstream_pages(), (2..=2, 0));
+        assert_eq!(query(3, 25, 1).upstream_pages(), (1..=1, 50));
+        assert_eq!(query(4, 30, 1).upstream_pages(), (1..=2, 90));
+        assert_eq!(query(1, 100, 3).upstream_pages(), (1..=3, 0));
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Some(1024));
+        assert_eq!(parse_size("1.5MB"), Some(1_572_864));
+        assert_eq!(parse_size("20 kb"), Some(20_480));
+        assert_eq!(parse_size("2GB"), Some(2 << 30));
+        assert_eq!(parse_size("MB"), None);
+        assert_eq!(parse_size("1.5 parsecs"), None);
+    }
+
+    #[test]
+    fn test_matches_year_range_and_size() {
+        let query = SearchQuery::from_filters(
+            "dune",
+            &filters(&[
+                ("year_from", "1990"),
+                ("year_to", "2000"),
+                ("max_size", "2MB"),
+            ]),
+        )
+        .unwrap();
+        assert!(query.matches(&book("1995", "1.5MB")));
+        assert!(query.matches(&book("2000, reprint", "2MB")));
+        assert!(!query.matches(&book("1989", "1.5MB")));
+        assert!(!query.matches(&book("2001", "1.5MB")));
+        assert!(!query.matches(&book("1995", "2.1MB")));
+        assert!(!query.matches(&book("", "1.5MB")));
+        assert!(SearchQuery::new("dune").matches(&book("", "")));
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{Context, Result};
+use axum::Router;
+use socket2::{Domain, Socket, Type};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use tokio::net::TcpListener;
+use tokio::task::JoinSet;
+
+/// Resolve the TCP addresses to listen on.
+///
+/// Each entry of `addresses` is either a full `host:port` pair (IPv6 hosts in
+/// brackets, e.g. `[::]:8084`) or a bare host, which is combined with `default_port`.
+/// Host names are resolved, so `localhost` may yield both an IPv4 and an IPv6 address.
+pub fn resolve_addresses(addresses: &[String], default_port: u16) -> Result<Vec<SocketAddr>> {
+    let mut resolved = Vec::new();
+    for address in addresses {
+        let socket_addrs: Vec<SocketAddr> = match address.to_socket_addrs() {
+            Ok(addrs) => addrs.collect(),
+            Err(_) => {
+                let host = address.trim_start_matches('[').trim_end_matches(']');
+                (host, default_port)
+                    .to_socket_addrs()
+                    .with_context(|| format!("Invalid listen address '{}'", address))?
+                    .collect()
+            }
+        };
+        for addr in socket_addrs {
+            if !resolved.contains(&addr) {
+                resolved.push(addr);
+            }
+        }
+    }
+    Ok(resolved)
+}
+
+/// Bind a TCP listener. IPv6 sockets are made IPv6-only, so `0.0.0.0` and `[::]`
+/// can be listened on side by side.
+fn bind_tcp(addr: SocketAddr) -> Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    Ok(TcpListener::from_std(socket.into())?)
+}
+
+/// The file of a bound Unix domain socket, removed again when dropped.
+#[cfg(unix)]
+struct SocketFile(PathBuf);
+
+#[cfg(unix)]
+impl Drop for SocketFile {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.0) {
+            tracing::warn!("Failed to remove socket {:?}: {}", self.0, e);
+        }
+    }
+}
+
+/// Bind a Unix domain socket, replacing a stale socket file left by a previous run.
+/// Any other file at `path` is left alone and fails the bind.
+#[cfg(unix)]
+fn bind_unix(path: &Path) -> Result<(tokio::net::UnixListener, SocketFile)> {
+    use std::os::unix::fs::FileTypeExt;
+
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove stale socket {:?}", path))?,
+        Ok(_) => anyhow::bail!("{:?} already exists and is not a socket", path),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e).with_context(|| format!("Failed to inspect {:?}", path)),
+    }
+    let listener = tokio::net::UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind Unix socket {:?}", path))?;
+    Ok((listener, SocketFile(path.to_path_buf())))
+}
+
+/// Wait for SIGINT or, on Unix, SIGTERM.
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(e) => {
+                tracing::warn!("Cannot listen for SIGTERM: {}", e);
+                std::future::pending::<()>().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Serve `app` on every TCP address and, if given, on a Unix domain socket.
+///
+/// All listeners are bound before any of them starts serving, so a bad address
+/// fails startup instead of leaving a half-running server. Returns on SIGINT or
+/// SIGTERM, after removing the Unix socket file.
+pub async fn serve(app: Router, addrs: &[SocketAddr], unix_socket: Option<&PathBuf>) -> Result<()> {
+    let mut servers = JoinSet::new();
+
+    let mut listeners = Vec::new();
+    for addr in addrs {
+        let listener = bind_tcp(*addr).with_context(|| format!("Failed to bind {}", addr))?;
+        listeners.push(listener);
+    }
+
+    #[cfg(unix)]
+    let mut _socket_file = None;
+    #[cfg(unix)]
+    if let Some(path) = unix_socket {
+        let (listener, socket_file) = bind_unix(path)?;
+        _socket_file = Some(socket_file);
+        tracing::info!("listening on unix:{}", path.display());
+        let app = app.clone();
+        servers.spawn(async move { axum::serve(listener, app).await });
+    }
+    #[cfg(not(unix))]
+    if unix_socket.is_some() {
+        anyhow::bail!("Unix domain sockets are not supported on this platform");
+    }
+
+    for listener in listeners {
+        tracing::info!("listening on {}", listener.local_addr()?);
+        let app = app.clone();
+        servers.spawn(async move { axum::serve(listener, app).await });
+    }
+
+    // Run until shutdown or until one of the servers stops, which only happens on
+    // an error
+    tokio::select! {
+        Some(result) = servers.join_next() => result??,
+        _ = shutdown_signal() => tracing::info!("Shutting down"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_addresses() {
+        let addresses = vec![
+            "0.0.0.0".to_string(),
+            "::".to_string(),
+            "[::1]:9000".to_string(),
+            "127.0.0.1:9001".to_string(),
+        ];
+        let resolved = resolve_addresses(&addresses, 5003).unwrap();
+
+        assert_eq!(
+            resolved,
+            vec![
+                "0.0.0.0:5003".parse::<SocketAddr>().unwrap(),
+                "[::]:5003".parse().unwrap(),
+                "[::1]:9000".parse().unwrap(),
+                "127.0.0.1:9001".parse().unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_resolve_addresses_deduplicates() {
+        let addresses = vec!["127.0.0.1".to_string(), "127.0.0.1:5003".to_string()];
+        let resolved = resolve_addresses(&addresses, 5003).unwrap();
+
+        assert_eq!(resolved.len(), 1);
+    }
+
+    #[test]
+    fn test_resolve_addresses_invalid() {
+        let addresses = vec!["not a host".to_string()];
+        assert!(resolve_addresses(&addresses, 5003).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bind_ipv4_and_ipv6_side_by_side() {
+        let v4 = bind_tcp("0.0.0.0:0".parse().unwrap()).unwrap();
+        let port = v4.local_addr().unwrap().port();
+        // Only meaningful where IPv6 is available
+        if let Ok(v6) = bind_tcp(SocketAddr::from(([0u16; 8], port))) {
+            assert_eq!(v6.local_addr().unwrap().port(), port);
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_serves_requests() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let path = std::env::temp_dir().join(format!("cwa-test-{}.sock", std::process::id()));
+        let app = Router::new().route("/ping", axum::routing::get(|| async { "pong" }));
+        let (listener, socket_file) = bind_unix(&path).unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut stream = tokio::net::UnixStream::connect(&path).await.unwrap();
+        stream
+            .write_all(b"GET /ping HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200"));
+        assert!(response.ends_with("pong"));
+        drop(socket_file);
+        assert!(!path.exists());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_unix_socket_replaces_only_sockets() {
+        let dir = std::env::temp_dir().join(format!("cwa-test-sockets-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // A socket left behind by a previous run is replaced
+        let stale = dir.join("stale.sock");
+        drop(std::os::unix::net::UnixListener::bind(&stale).unwrap());
+        let replaced = bind_unix(&stale).unwrap();
+
+        // Anything else is not touched
+        let file = dir.join("data.txt");
+        std::fs::write(&file, "keep me").unwrap();
+        assert!(bind_unix(&file).is_err());
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "keep me");
+
+        drop(replaced);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,445 @@
+use crate::book_manager;
+use crate::config::Config;
+use crate::models::BookInfo;
+use crate::opds::OpdsCatalogue;
+use crate::search::{SearchQuery, SearchResults};
+use crate::state::AppState;
+use anyhow::{anyhow, Result};
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::task::JoinSet;
+
+/// The future returned by [`BookSource`] methods; boxed so sources can be trait objects.
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// Names accepted in `BOOK_SOURCES`.
+pub const SOURCE_NAMES: &[&str] = &[AnnasArchive::NAME, OpdsCatalogue::NAME];
+
+/// A catalogue books can be searched in and downloaded from.
+///
+/// Ids passed to and returned by a source are its own; [`Sources`] qualifies them
+/// with the source's name where needed.
+pub trait BookSource: fmt::Debug + Send + Sync {
+    /// The name used in `BOOK_SOURCES` and in qualified book ids.
+    fn name(&self) -> &str;
+
+    fn search<'a>(
+        &'a self,
+        state: &'a AppState,
+        query: &'a SearchQuery,
+        bypass_cache: bool,
+    ) -> BoxFuture<'a, Result<SearchResults>>;
+
+    fn get_info<'a>(
+        &'a self,
+        state: &'a AppState,
+        id: &'a str,
+        bypass_cache: bool,
+    ) -> BoxFuture<'a, Result<BookInfo>>;
+
+    /// The URLs to try, in order, to download `book`.
+    #[allow(dead_code)]
+    fn resolve_downloads<'a>(
+        &'a self,
+        state: &'a AppState,
+        book: &'a BookInfo,
+    ) -> BoxFuture<'a, Result<Vec<String>>>;
+
+    /// `url`, one of this source's links, with any credentials needed to fetch it.
+    fn authorize_url(&self, url: &str) -> String {
+        url.to_string()
+    }
+}
+
+/// Anna's Archive, through the configured mirrors.
+#[derive(Debug)]
+pub struct AnnasArchive;
+
+impl AnnasArchive {
+    pub const NAME: &'static str = "annas_archive";
+}
+
+impl BookSource for AnnasArchive {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn search<'a>(
+        &'a self,
+        state: &'a AppState,
+        query: &'a SearchQuery,
+        bypass_cache: bool,
+    ) -> BoxFuture<'a, Result<SearchResults>> {
+        Box::pin(book_manager::search_books(state, query, None, bypass_cache))
+    }
+
+    fn get_info<'a>(
+        &'a self,
+        state: &'a AppState,
+        id: &'a str,
+        bypass_cache: bool,
+    ) -> BoxFuture<'a, Result<BookInfo>> {
+        Box::pin(book_manager::get_book_info(state, id, None, bypass_cache))
+    }
+
+    fn resolve_downloads<'a>(
+        &'a self,
+        _state: &'a AppState,
+        book: &'a BookInfo,
+    ) -> BoxFuture<'a, Result<Vec<String>>> {
+        Box::pin(async move { Ok(book.download_urls.clone()) })
+    }
+}
+
+/// The enabled sources, in the order of `BOOK_SOURCES`.
+///
+/// Books from Anna's Archive keep their bare MD5 id; books from any other source
+/// get ids of the form `<source>:<id>`.
+#[derive(Clone, Debug)]
+pub struct Sources {
+    sources: Vec<Arc<dyn BookSource>>,
+}
+
+impl Sources {
+    pub fn new(sources: Vec<Arc<dyn BookSource>>) -> Self {
+        Sources { sources }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        let sources = config
+            .book_sources
+            .iter()
+            .filter_map(|name| match name.as_str() {
+                AnnasArchive::NAME => Some(Arc::new(AnnasArchive) as Arc<dyn BookSource>),
+                OpdsCatalogue::NAME => {
+                    let url = config.opds_url.as_deref().unwrap_or_default();
+                    match OpdsCatalogue::new(url) {
+                        Ok(catalogue) => Some(Arc::new(catalogue) as Arc<dyn BookSource>),
+                        Err(e) => {
+                            tracing::error!(error = %e, "OPDS source disabled");
+                            None
+                        }
+                    }
+                }
+                _ => None,
+            })
+            .collect();
+        Sources::new(sources)
+    }
+
+    /// The source a qualified id belongs to, and the id within that source.
+    fn route<'a>(&self, id: &'a str) -> Result<(&Arc<dyn BookSource>, &'a str)> {
+        let qualified = id.split_once(':').and_then(|(name, local_id)| {
+            self.sources
+                .iter()
+                .find(|source| source.name() == name)
+                .map(|source| (source, local_id))
+        });
+        qualified
+            .or_else(|| {
+                self.sources
+                    .iter()
+                    .find(|source| source.name() == AnnasArchive::NAME)
+                    .map(|source| (source, id))
+            })
+            .ok_or_else(|| anyhow!("No enabled source has book {}", id))
+    }
+
+    /// Search every source at once, and merge their results in source order.
+    ///
+    /// A failing source is logged and left out, unless every source fails.
+    pub async fn search(
+        &self,
+        state: &AppState,
+        query: &SearchQuery,
+        bypass_cache: bool,
+    ) -> Result<SearchResults> {
+        let mut tasks = JoinSet::new();
+        for (index, source) in self.sources.iter().enumerate() {
+            let (source, state, query) = (source.clone(), state.clone(), query.clone());
+            tasks.spawn(async move {
+                let results = source.search(&state, &query, bypass_cache).await;
+                (index, results.map(|results| tag(source.as_ref(), results)))
+            });
+        }
+
+        let mut outcomes = Vec::new();
+        while let Some(outcome) = tasks.join_next().await {
+            outcomes.push(outcome?);
+        }
+        outcomes.sort_by_key(|(index, _)| *index);
+
+        let mut results = Vec::new();
+        let mut first_error = None;
+        for (index, outcome) in outcomes {
+            match outcome {
+                Ok(source_results) => results.push(source_results),
+                Err(e) if self.sources.len() > 1 => {
+                    tracing::warn!(source = self.sources[index].name(), error = %e, "Source search failed");
+                    first_error.get_or_insert(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        match (results.is_empty(), first_error) {
+            (true, Some(e)) => Err(e),
+            _ => Ok(merge(query, results)),
+        }
+    }
+
+    pub async fn get_info(
+        &self,
+        state: &AppState,
+        id: &str,
+        bypass_cache: bool,
+    ) -> Result<BookInfo> {
+        let (source, local_id) = self.route(id)?;
+        let book = source.get_info(state, local_id, bypass_cache).await?;
+        Ok(qualify(source.as_ref(), book))
+    }
+
+    #[allow(dead_code)]
+    pub async fn resolve_downloads(
+        &self,
+        state: &AppState,
+        book: &BookInfo,
+    ) -> Result<Vec<String>> {
+        let (source, local_id) = self.route(&book.id)?;
+        let book = BookInfo {
+            id: local_id.to_string(),
+            ..book.clone()
+        };
+        source.resolve_downloads(state, &book).await
+    }
+
+    /// `url`, a link of the book `id`, with the credentials its source needs.
+    pub fn authorize_url(&self, id: &str, url: &str) -> String {
+        match self.route(id) {
+            Ok((source, _)) => source.authorize_url(url),
+            Err(_) => url.to_string(),
+        }
+    }
+}
+
+/// Record where `book` came from, and qualify its id if needed.
+fn qualify(source: &dyn BookSource, mut book: BookInfo) -> BookInfo {
+    if source.name() != AnnasArchive::NAME {
+        book.id = format!("{}:{}", source.name(), book.id);
+    }
+    book.source = source.name().to_string();
+    book
+}
+
+fn tag(source: &dyn BookSource, mut results: SearchResults) -> SearchResults {
+    results.results = results
+        .results
+        .into_iter()
+        .map(|book| qualify(source, book))
+        .collect();
+    results
+}
+
+/// Keys identifying the same book across sources: its MD5 and ISBNs.
+fn duplicate_keys(book: &BookInfo) -> Vec<String> {
+    let md5 = book
+        .metadata
+        .identifiers
+        .md5
+        .iter()
+        .map(|md5| format!("md5:{}", md5));
+    let isbns = book
+        .metadata
+        .isbns
+        .iter()
+        .map(|isbn| format!("isbn:{}", isbn.isbn_13));
+    md5.chain(isbns).collect()
+}
+
+/// Concatenate the results of several sources, dropping books already returned by
+/// an earlier source. The total is only known when a single source answered.
+fn merge(query: &SearchQuery, mut results: Vec<SearchResults>) -> SearchResults {
+    if results.len() == 1 {
+        return results.remove(0);
+    }
+    let mut seen = HashSet::new();
+    let mut merged = SearchResults {
+        page: query.page,
+        page_size: query.page_size,
+        ..Default::default()
+    };
+    for source_results in results {
+        merged.has_more |= source_results.has_more;
+        for book in source_results.results {
+            let keys = duplicate_keys(&book);
+            if keys.iter().any(|key| seen.contains(key)) {
+                continue;
+            }
+            seen.extend(keys);
+            merged.results.push(book);
+        }
+    }
+    merged
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::metadata::Isbn;
+    use crate::state::test_state;
+    use tokio::test;
+
+    /// A source answering every search with the same books.
+    #[derive(Debug)]
+    struct FixedSource {
+        name: &'static str,
+        books: Vec<BookInfo>,
+        fails: bool,
+    }
+
+    impl BookSource for FixedSource {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn search<'a>(
+            &'a self,
+            _state: &'a AppState,
+            query: &'a SearchQuery,
+            _bypass_cache: bool,
+        ) -> BoxFuture<'a, Result<SearchResults>> {
+            Box::pin(async move {
+                if self.fails {
+                    return Err(anyhow!("{} is down", self.name));
+                }
+                Ok(SearchResults {
+                    results: self.books.clone(),
+                    page: query.page,
+                    page_size: query.page_size,
+                    has_more: false,
+                    total: Some(self.books.len() as u64),
+                })
+            })
+        }
+
+        fn get_info<'a>(
+            &'a self,
+            _state: &'a AppState,
+            id: &'a str,
+            _bypass_cache: bool,
+        ) -> BoxFuture<'a, Result<BookInfo>> {
+            Box::pin(async move {
+                self.books
+                    .iter()
+                    .find(|book| book.id == id)
+                    .cloned()
+                    .ok_or_else(|| anyhow!("no book {}", id))
+            })
+        }
+
+        fn resolve_downloads<'a>(
+            &'a self,
+            _state: &'a AppState,
+            book: &'a BookInfo,
+        ) -> BoxFuture<'a, Result<Vec<String>>> {
+            Box::pin(async move { Ok(vec![format!("https://{}/{}", self.name, book.id)]) })
+        }
+    }
+
+    fn book(id: &str, md5: Option<&str>, isbn: Option<&str>) -> BookInfo {
+        let mut book = BookInfo::new(id, id);
+        book.metadata.identifiers.md5 = md5.map(str::to_string);
+        book.metadata.isbns = isbn.and_then(Isbn::parse).into_iter().collect();
+        book
+    }
+
+    pub(crate) fn source(name: &'static str, books: Vec<BookInfo>) -> Arc<dyn BookSource> {
+        Arc::new(FixedSource {
+            name,
+            books,
+            fails: false,
+        })
+    }
+
+    fn ids(results: &SearchResults) -> Vec<&str> {
+        results
+            .results
+            .iter()
+            .map(|book| book.id.as_str())
+            .collect()
+    }
+
+    #[test]
+    async fn test_search_merges_and_dedupes_sources() {
+        let state = test_state(&[]);
+        let md5 = "9320e010092ad5cde279f733bdda3a2f";
+        let sources = Sources::new(vec![
+            source(AnnasArchive::NAME, vec![book(md5, Some(md5), None)]),
+            source(
+                "other",
+                vec![
+                    book("1", Some(md5), None),
+                    book("2", None, Some("9780261103207")),
+                    book("3", None, Some("0261103202")),
+                ],
+            ),
+        ]);
+
+        let results = sources
+            .search(&state, &SearchQuery::new("dune"), false)
+            .await
+            .unwrap();
+        assert_eq!(ids(&results), vec![md5, "other:2"]);
+        assert_eq!(results.results[1].source, "other");
+        assert_eq!(results.total, None);
+    }
+
+    #[test]
+    async fn test_search_skips_failing_sources() {
+        let state = test_state(&[]);
+        let failing = Arc::new(FixedSource {
+            name: "down",
+            books: vec![],
+            fails: true,
+        });
+        let sources = Sources::new(vec![
+            failing.clone(),
+            source("up", vec![book("1", None, None)]),
+        ]);
+        let results = sources
+            .search(&state, &SearchQuery::new("dune"), false)
+            .await
+            .unwrap();
+        assert_eq!(ids(&results), vec!["up:1"]);
+
+        let sources = Sources::new(vec![failing]);
+        assert!(sources
+            .search(&state, &SearchQuery::new("dune"), false)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    async fn test_ids_are_routed_to_their_source() {
+        let state = test_state(&[]);
+        let sources = Sources::new(vec![
+            source(AnnasArchive::NAME, vec![book("abc", None, None)]),
+            source("other", vec![book("abc", None, None)]),
+        ]);
+
+        let book = sources.get_info(&state, "other:abc", false).await.unwrap();
+        assert_eq!(book.id, "other:abc");
+        assert_eq!(
+            sources.resolve_downloads(&state, &book).await.unwrap(),
+            vec!["https://other/abc"]
+        );
+        let book = sources.get_info(&state, "abc", false).await.unwrap();
+        assert_eq!(book.id, "abc");
+        assert_eq!(book.source, AnnasArchive::NAME);
+
+        let sources = Sources::new(vec![source("other", vec![])]);
+        assert!(sources.get_info(&state, "abc", false).await.is_err());
+    }
+}
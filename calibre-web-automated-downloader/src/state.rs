@@ -0,0 +1,66 @@
+use crate::cache::PageCache;
+use crate::config::Config;
+use crate::covers::CoverCache;
+use crate::library::Library;
+use crate::limiter::HostLimiter;
+use crate::mirrors::MirrorPool;
+use crate::models::BookQueue;
+use crate::sources::Sources;
+use reqwest::Client;
+use std::sync::{Arc, RwLock};
+
+/// Everything the handlers and background tasks share: the configuration and the
+/// services built from it.
+///
+/// Cloning is cheap, as every part is reference counted. Handlers receive it through
+/// axum's `State`; tests build their own from an isolated configuration.
+#[derive(Clone, Debug)]
+pub struct AppState {
+    /// The current configuration; swapped out as a whole when settings are reloaded.
+    config: Arc<RwLock<Arc<Config>>>,
+    /// Client for all outbound requests, so they share one connection pool.
+    pub client: Client,
+    pub queue: Arc<BookQueue>,
+    pub limiter: Arc<HostLimiter>,
+    pub mirrors: Arc<MirrorPool>,
+    pub cache: Arc<PageCache>,
+    /// Cover images served by `/api/cover`.
+    pub covers: Arc<CoverCache>,
+    /// Books already owned, for duplicate checks.
+    pub library: Arc<Library>,
+    /// Where books are searched for and downloaded from.
+    pub sources: Sources,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Self {
+        AppState {
+            client: Client::new(),
+            queue: Arc::new(BookQueue::from_config(&config)),
+            limiter: Arc::new(HostLimiter::from_config(&config)),
+            mirrors: Arc::new(MirrorPool::new(config.aa_mirror_urls.clone())),
+            cache: Arc::new(PageCache::from_config(&config)),
+            covers: Arc::new(CoverCache::from_config(&config)),
+            library: Arc::new(Library::default()),
+            sources: Sources::from_config(&config),
+            config: Arc::new(RwLock::new(Arc::new(config))),
+        }
+    }
+
+    /// The current configuration. Take it once per operation, so a reload in the
+    /// middle of it does not mix old and new settings.
+    pub fn config(&self) -> Arc<Config> {
+        self.config.read().unwrap().clone()
+    }
+
+    /// Replace the configuration for everything that reads it from now on.
+    pub fn set_config(&self, config: Config) {
+        *self.config.write().unwrap() = Arc::new(config);
+    }
+}
+
+/// A state with an isolated test configuration; see [`crate::config::test_config`].
+#[cfg(test)]
+pub fn test_state(settings: &[(&str, &str)]) -> AppState {
+    AppState::new(crate::config::test_config(settings))
+}
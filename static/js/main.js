@@ -25,11 +25,12 @@ document.addEventListener('DOMContentLoaded', () => {
 
     // Constants
     const REFRESH_INTERVAL = 60000; // 60 seconds
+    // Relative to the page, so the app works under any URL_BASE
     const API_ENDPOINTS = {
-        search: '/request/api/search',
-        info: '/request/api/info',
-        download: '/request/api/download',
-        status: '/request/api/status'
+        search: 'api/search',
+        info: 'api/info',
+        download: 'api/download',
+        status: 'api/status'
     };
 
     // Utility Functions
@@ -97,7 +98,7 @@ document.addEventListener('DOMContentLoaded', () => {
                     `${API_ENDPOINTS.search}?query=${encodeURIComponent(query)}`
                 );
                 
-                this.displayResults(data);
+                this.displayResults(data.results);
             } catch (error) {
                 this.handleSearchError(error);
             } finally {
@@ -231,6 +232,7 @@ document.addEventListener('DOMContentLoaded', () => {
                         <p><strong>Language:</strong> ${book.language || 'N/A'}</p>
                         <p><strong>Format:</strong> ${book.format || 'N/A'}</p>
                         <p><strong>Size:</strong> ${book.size || 'N/A'}</p>
+                        ${this.generateMetadataHTML(book.metadata)}
                     </div>
                 </div>
                 ${this.generateInfoList(book.info)}
@@ -241,6 +243,24 @@ document.addEventListener('DOMContentLoaded', () => {
             `;
         },
 
+        generateMetadataHTML(metadata) {
+            if (!metadata) return '';
+
+            const lines = [];
+            if (metadata.series) {
+                const index = metadata.series.index != null ? ` #${metadata.series.index}` : '';
+                lines.push(`<p><strong>Series:</strong> ${metadata.series.name}${index}</p>`);
+            }
+            if (metadata.isbns && metadata.isbns.length) {
+                const isbns = metadata.isbns.map(isbn => isbn.isbn_13).join(', ');
+                lines.push(`<p><strong>ISBN:</strong> ${isbns}</p>`);
+            }
+            if (metadata.pages) {
+                lines.push(`<p><strong>Pages:</strong> ${metadata.pages}</p>`);
+            }
+            return lines.join('');
+        },
+
         generateInfoList(info) {
             if (!info) return '';
 
@@ -325,7 +345,7 @@ document.addEventListener('DOMContentLoaded', () => {
             let titleElement;
             if (status.toLowerCase().includes('available')) {
                 titleElement = utils.createElement('a', {
-                    href: `/request/api/localdownload?id=${book.id}`,
+                    href: `api/localdownload?id=${book.id}`,
                     target: '_blank',
                     textContent: book.title || 'N/A'
                 });